@@ -0,0 +1,68 @@
+//! The information the bootloader passes to the kernel.
+//!
+//! `kernel_main` only borrows the [`BootInfo`], so the parts that are needed after
+//! early boot are copied into [`BootData`] instead of keeping the reference around.
+
+use crate::mem::PhysicalAddress;
+use crate::mem::VirtualAddress;
+use bootloader_api::info::FrameBufferInfo;
+use bootloader_api::BootInfo;
+use core::ops::Range;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+
+static BOOT_DATA: Spinlock<BootData> = const_spinlock(BootData {
+    framebuffer: None,
+    rsdp_addr: None,
+});
+
+extern "C" {
+    /// The start of the kernel image, defined by the linker.
+    static __ehdr_start: u8;
+    /// The end of the kernel image, defined by the linker.
+    static _end: u8;
+}
+
+/// The parts of the [`BootInfo`] that are used after early boot.
+#[derive(Debug, Clone, Copy)]
+pub struct BootData {
+    framebuffer: Option<(VirtualAddress, FrameBufferInfo)>,
+    rsdp_addr: Option<u64>,
+}
+
+impl BootData {
+    /// Copies the needed parts of `boot_info`. Until this is called, all accessors
+    /// return `None`.
+    pub fn init(boot_info: &BootInfo) {
+        let framebuffer = boot_info
+            .framebuffer
+            .as_ref()
+            .map(|fb| (VirtualAddress::new(fb.buffer().as_ptr() as u64), fb.info()));
+
+        *BOOT_DATA.lock() = BootData {
+            framebuffer,
+            rsdp_addr: boot_info.rsdp_addr.into_option(),
+        };
+    }
+
+    /// Returns the virtual address and the layout of the framebuffer, if there is one.
+    pub fn framebuffer() -> Option<(VirtualAddress, FrameBufferInfo)> {
+        BOOT_DATA.lock().framebuffer
+    }
+
+    /// Returns the physical address of the ACPI RSDP, if the bootloader found it.
+    pub fn rsdp_addr() -> Option<PhysicalAddress> {
+        BOOT_DATA.lock().rsdp_addr.map(PhysicalAddress::new_abs)
+    }
+
+    /// Returns the virtual addresses the kernel image is loaded at.
+    ///
+    /// The bootloader doesn't pass these, so they come from the linker instead and are
+    /// available even before [`BootData::init`].
+    pub fn kernel_image_range() -> Range<VirtualAddress> {
+        // SAFETY: only the addresses of the symbols are taken, they are never read.
+        let (start, end) = unsafe { (core::ptr::addr_of!(__ehdr_start), core::ptr::addr_of!(_end)) };
+
+        VirtualAddress::new(start as u64)..VirtualAddress::new(end as u64)
+    }
+}
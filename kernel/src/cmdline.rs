@@ -0,0 +1,145 @@
+//! The kernel command line.
+//!
+//! The bootloader does not pass a command line to the kernel, so it is baked into the
+//! kernel at build time from the `PINTOS_ARGS` environment variable instead, e.g.
+//! `PINTOS_ARGS="-ul 64" cargo run`.
+//!
+//! The command line consists of options, which configure the kernel (see
+//! [`KernelConfig`]), and of actions, which are run after booting. Options taking a
+//! value accept it both as `<name> <value>` and as `<name>=<value>`, if an option is
+//! given multiple times, the last occurrence wins. Flags like `-f` don't accept a value.
+
+use arrayvec::ArrayVec;
+use lazy_static::lazy_static;
+
+/// The maximum length of the command line in bytes, as in the original Pintos.
+const MAX_LEN: usize = 128;
+/// The maximum number of whitespace separated arguments that fit into [`MAX_LEN`].
+const MAX_ARGS: usize = MAX_LEN.div_ceil(2);
+
+const RAW: &str = match option_env!("PINTOS_ARGS") {
+    Some(args) => args,
+    None => "",
+};
+
+// Reject an oversized command line when building instead of at boot.
+const _: () = assert!(RAW.len() <= MAX_LEN, "PINTOS_ARGS must not be longer than 128 bytes");
+
+lazy_static! {
+    static ref CONFIG: KernelConfig = KernelConfig::parse(RAW);
+}
+
+/// The configuration of the kernel, as given by the options on the command line.
+#[derive(Debug, Clone)]
+pub struct KernelConfig {
+    /// The maximum number of pages to put into the user pool (`-ul <pages>`).
+    pub user_page_limit: u64,
+    /// The maximum number of kernel pool pages the heap may use (`-hl <pages>`).
+    pub heap_page_limit: usize,
+    /// The requested timer frequency in Hz (`-timer-hz <hz>`).
+    pub timer_freq: Option<u32>,
+    /// Whether the filesystem should be formatted during boot (`-f`). Without it, the
+    /// existing filesystem on the filesys device is mounted.
+    pub format_filesys: bool,
+    /// Whether to boot without initializing the disks and the filesystem (`-no-fs`), for
+    /// actions that don't need them. Reserved for now: neither is initialized yet, so the
+    /// flag doesn't change anything so far.
+    pub no_filesys: bool,
+    /// Whether the timer should print a heartbeat every second (`-heartbeat`).
+    pub heartbeat: bool,
+    /// The remaining arguments, which are the actions to run after booting.
+    pub actions: ArrayVec<&'static str, MAX_ARGS>,
+}
+
+impl KernelConfig {
+    /// Returns the configuration of the kernel, parsing the command line on first use.
+    ///
+    /// # Panics
+    /// Panics if an option is missing its value, the value is invalid or an option that
+    /// takes no value is given one.
+    pub fn get() -> &'static KernelConfig {
+        &CONFIG
+    }
+
+    fn parse(raw: &'static str) -> Self {
+        let mut config = Self {
+            user_page_limit: u64::MAX,
+            heap_page_limit: usize::MAX,
+            timer_freq: None,
+            format_filesys: false,
+            no_filesys: false,
+            heartbeat: false,
+            actions: ArrayVec::new(),
+        };
+
+        let mut args = raw.split_ascii_whitespace();
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg, None),
+            };
+            let mut value = || match inline_value.or_else(|| args.next()) {
+                Some(value) => value,
+                None => panic!("missing value for {name}"),
+            };
+            let flag = || match inline_value {
+                Some(value) => panic!("{name} doesn't take a value, got '{value}'"),
+                None => true,
+            };
+
+            match name {
+                "-f" => config.format_filesys = flag(),
+                "-no-fs" => config.no_filesys = flag(),
+                "-heartbeat" => config.heartbeat = flag(),
+                "-ul" => config.user_page_limit = parse_number(name, value()),
+                "-hl" => config.heap_page_limit = parse_number(name, value()),
+                "-timer-hz" => config.timer_freq = Some(parse_number(name, value())),
+                // The command line is at most `MAX_LEN` bytes, so all arguments fit.
+                _ => config.actions.push(arg),
+            }
+        }
+
+        config
+    }
+}
+
+/// Parses the `value` of the option `name` as a number.
+///
+/// # Panics
+/// Panics if the value is not a number.
+fn parse_number<T: core::str::FromStr>(name: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(v) => v,
+        Err(_) => panic!("invalid value '{value}' for {name}, expected a number"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_and_actions() {
+        let config = KernelConfig::parse("-f -ul 64 run -hl=8 -heartbeat -timer-hz=250 uptime -x");
+
+        assert!(config.format_filesys);
+        assert!(!config.no_filesys);
+        assert!(config.heartbeat);
+        assert_eq!(config.user_page_limit, 64);
+        assert_eq!(config.heap_page_limit, 8);
+        assert_eq!(config.timer_freq, Some(250));
+        assert_eq!(config.actions.as_slice(), ["run", "uptime", "-x"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "-f doesn't take a value, got 'yes'")]
+    fn flag_with_value() {
+        KernelConfig::parse("-f=yes");
+    }
+
+    #[test]
+    #[should_panic(expected = "missing value for -ul")]
+    fn missing_value() {
+        KernelConfig::parse("-ul");
+    }
+}
@@ -0,0 +1,77 @@
+/// The size of a block device sector in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The index of a sector on a block device.
+pub type SectorIdx = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockError {
+    /// The accessed sectors lie (partially) outside of the device.
+    OutOfRange,
+    /// The buffer is not exactly as large as the accessed sectors.
+    BufferSize,
+    /// The device failed to complete the transfer.
+    Io,
+}
+
+/// A device that is accessed in units of [`SECTOR_SIZE`] bytes.
+pub trait BlockDevice {
+    /// Returns the number of sectors on the device.
+    fn sector_count(&self) -> SectorIdx;
+
+    /// Reads the sector `sector` into `buf`.
+    fn read(&self, sector: SectorIdx, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), BlockError>;
+
+    /// Writes `buf` to the sector `sector`.
+    fn write(&self, sector: SectorIdx, buf: &[u8; SECTOR_SIZE]) -> Result<(), BlockError>;
+
+    /// Reads `count` consecutive sectors starting at `start` into `buf`, which must be
+    /// exactly `count * SECTOR_SIZE` bytes large.
+    ///
+    /// The default implementation reads one sector at a time, devices that support
+    /// multi-sector transfers should override this.
+    fn read_range(&self, start: SectorIdx, count: SectorIdx, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_range(self.sector_count(), start, count, buf.len())?;
+
+        for (sector, chunk) in (start..).zip(buf.chunks_exact_mut(SECTOR_SIZE)) {
+            self.read(sector, chunk.try_into().unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf`, which must be exactly `count * SECTOR_SIZE` bytes large, to the
+    /// `count` consecutive sectors starting at `start`.
+    ///
+    /// The default implementation writes one sector at a time, devices that support
+    /// multi-sector transfers should override this.
+    fn write_range(&self, start: SectorIdx, count: SectorIdx, buf: &[u8]) -> Result<(), BlockError> {
+        check_range(self.sector_count(), start, count, buf.len())?;
+
+        for (sector, chunk) in (start..).zip(buf.chunks_exact(SECTOR_SIZE)) {
+            self.write(sector, chunk.try_into().unwrap())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the `count` sectors starting at `start` lie on a device with
+/// `sector_count` sectors and that a buffer of `buf_len` bytes holds exactly them.
+pub fn check_range(
+    sector_count: SectorIdx,
+    start: SectorIdx,
+    count: SectorIdx,
+    buf_len: usize,
+) -> Result<(), BlockError> {
+    match start.checked_add(count) {
+        Some(end) if end <= sector_count => {}
+        _ => return Err(BlockError::OutOfRange),
+    }
+
+    if buf_len != (count as usize) * SECTOR_SIZE {
+        return Err(BlockError::BufferSize);
+    }
+
+    Ok(())
+}
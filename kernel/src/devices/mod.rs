@@ -1,3 +1,7 @@
+pub use block::*;
+pub use ramdisk::*;
 pub use timer::*;
 
-mod timer;
\ No newline at end of file
+mod block;
+mod ramdisk;
+mod timer;
@@ -0,0 +1,73 @@
+use crate::devices::check_range;
+use crate::devices::BlockDevice;
+use crate::devices::BlockError;
+use crate::devices::SectorIdx;
+use crate::devices::SECTOR_SIZE;
+use alloc::vec;
+use alloc::vec::Vec;
+use spinning_top::Spinlock;
+
+/// A block device that keeps its sectors on the heap.
+///
+/// This allows testing code that needs a disk without a real drive.
+#[derive(Debug)]
+pub struct RamDisk {
+    data: Spinlock<Vec<u8>>,
+    sector_count: SectorIdx,
+}
+
+impl RamDisk {
+    /// Creates a new zero-filled [`RamDisk`] with `sector_count` sectors.
+    pub fn new(sector_count: SectorIdx) -> Self {
+        Self {
+            data: Spinlock::new(vec![0; (sector_count as usize) * SECTOR_SIZE]),
+            sector_count,
+        }
+    }
+
+    /// Creates a new [`RamDisk`] holding `bytes`, padded with zeros to whole sectors.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let sector_count = bytes.len().div_ceil(SECTOR_SIZE);
+        let mut data = bytes.to_vec();
+        data.resize(sector_count * SECTOR_SIZE, 0);
+
+        Self {
+            data: Spinlock::new(data),
+            sector_count: sector_count.try_into().expect("too much data for a RamDisk"),
+        }
+    }
+
+    /// Returns the byte range of the `count` sectors starting at `start`.
+    fn byte_range(start: SectorIdx, count: SectorIdx) -> core::ops::Range<usize> {
+        let start = (start as usize) * SECTOR_SIZE;
+        start..(start + (count as usize) * SECTOR_SIZE)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_count(&self) -> SectorIdx {
+        self.sector_count
+    }
+
+    fn read(&self, sector: SectorIdx, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), BlockError> {
+        self.read_range(sector, 1, buf)
+    }
+
+    fn write(&self, sector: SectorIdx, buf: &[u8; SECTOR_SIZE]) -> Result<(), BlockError> {
+        self.write_range(sector, 1, buf)
+    }
+
+    fn read_range(&self, start: SectorIdx, count: SectorIdx, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_range(self.sector_count, start, count, buf.len())?;
+        buf.copy_from_slice(&self.data.lock()[Self::byte_range(start, count)]);
+
+        Ok(())
+    }
+
+    fn write_range(&self, start: SectorIdx, count: SectorIdx, buf: &[u8]) -> Result<(), BlockError> {
+        check_range(self.sector_count, start, count, buf.len())?;
+        self.data.lock()[Self::byte_range(start, count)].copy_from_slice(buf);
+
+        Ok(())
+    }
+}
@@ -1,34 +1,204 @@
+use crate::threads::InterruptContext;
 use crate::threads::Interrupts;
+use crate::threads::Thread;
+use core::fmt::Display;
+use core::sync::atomic::fence;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
 use spinning_top::const_spinlock;
 use spinning_top::Spinlock;
 use x86_64::instructions::port::Port;
-use x86_64::structures::idt::InterruptStackFrame;
 
-/// The number of ticks since the OS has booted. We use a
-/// `Spinlock<u64>` instead of an `AtomicU64` here for
-/// compatibility reasons.
-static TICKS: Spinlock<u64> = const_spinlock(0);
+/// The number of ticks of the [`PitTimer`] since the OS has booted, protected by a
+/// seqlock. We use two 32-bit halves instead of an `AtomicU64` here for compatibility
+/// reasons.
+///
+/// The timer interrupt handler is the only writer. It makes [`TICKS_SEQ`] odd while it
+/// updates the halves and even again afterwards, and readers retry until they read both
+/// halves under the same even sequence number. Unlike with a lock, readers never block
+/// the handler, and the handler is entered through an interrupt gate, so readers can't
+/// interrupt it halfway either.
+static TICKS_SEQ: AtomicU32 = AtomicU32::new(0);
+static TICKS_LO: AtomicU32 = AtomicU32::new(0);
+static TICKS_HI: AtomicU32 = AtomicU32::new(0);
+
+/// The frequency the timer was configured with in `Timer::init`.
+static FREQ: AtomicU32 = AtomicU32::new(Timer::DEFAULT_FREQ);
+
+/// The timer that drives the timer interrupt, chosen in `Timer::init`.
+static SOURCE: Spinlock<&'static dyn TimerSource> = const_spinlock(&PitTimer);
+
+/// Whether to print a heartbeat every second, see [`Timer::set_heartbeat`].
+static HEARTBEAT: AtomicBool = AtomicBool::new(false);
+
+/// A hardware timer that can drive the timer interrupt, e.g. the PIT, the HPET or the
+/// local APIC timer.
+pub trait TimerSource: Sync {
+    /// Returns the name of the timer, which is also used for its interrupt handler.
+    fn name(&self) -> &'static str;
+
+    /// Programs the timer to interrupt `freq` times per second and registers an
+    /// interrupt handler that calls [`Timer::on_tick`] on every interrupt.
+    fn init(&self, freq: u32);
+
+    /// Returns the number of ticks since the timer was initialized.
+    fn ticks(&self) -> u64;
+}
 
 pub struct Timer;
 
 impl Timer {
-    pub const FREQ: u32 = 100;
+    /// The default timer frequency in Hz.
+    pub const DEFAULT_FREQ: u32 = 100;
+    /// The lowest supported frequency. The PIT can't count slower than about 18.2 Hz.
+    pub const MIN_FREQ: u32 = 19;
+    /// The highest supported frequency. Faster ticks leave too little time between
+    /// the interrupts for anything else.
+    pub const MAX_FREQ: u32 = 1000;
+
+    /// Sets up the [`PitTimer`] to interrupt `freq` times per second.
+    ///
+    /// Frequencies outside of `MIN_FREQ..=MAX_FREQ` are reported and clamped to that range.
+    pub fn init(freq: u32) {
+        Self::init_with(&PitTimer, freq);
+    }
+
+    /// Like [`Timer::init`], but uses `source` as the timer.
+    pub fn init_with(source: &'static dyn TimerSource, freq: u32) {
+        let clamped = freq.clamp(Self::MIN_FREQ, Self::MAX_FREQ);
+        if clamped != freq {
+            crate::println!(
+                "timer frequency of {freq} Hz is outside of {}..={} Hz, using {clamped} Hz",
+                Self::MIN_FREQ,
+                Self::MAX_FREQ
+            );
+        }
+        FREQ.store(clamped, Ordering::Relaxed);
+
+        let guard = Interrupts::disable_guarded();
+        *SOURCE.lock() = source;
+        drop(guard);
+
+        source.init(clamped);
+    }
+
+    /// Enables or disables printing a `.` to the console every second from the timer
+    /// interrupt, which shows whether a kernel that seems stuck still gets interrupts.
+    pub fn set_heartbeat(enabled: bool) {
+        HEARTBEAT.store(enabled, Ordering::Relaxed);
+    }
 
-    /// Sets up the timer to interrupt Self::FREQ times per second and registers
-    /// the corresponding interrupt handler.
-    pub fn init() {
-        PIT::configure_channel(TimerChannel::Channel0, TimerMode::Mode2, Self::FREQ);
-        Interrupts::register_handler(0x20, Self::on_interrupt, "8254 Timer");
+    /// Returns the number of timer interrupts per second.
+    pub fn freq() -> u32 {
+        FREQ.load(Ordering::Relaxed)
     }
 
     /// Returns the number of ticks since the OS has booted.
     pub fn ticks() -> u64 {
-        *TICKS.lock()
+        let guard = Interrupts::disable_guarded();
+        let source = *SOURCE.lock();
+        drop(guard);
+
+        source.ticks()
+    }
+
+    /// Returns the time since the OS has booted.
+    pub fn uptime() -> Duration {
+        Self::ticks_to_duration(Self::ticks(), Self::freq())
     }
 
-    fn on_interrupt(frame: InterruptStackFrame) {
-        *TICKS.lock() += 1;
-        // ToDo: thread_tick
+    /// Converts a number of timer ticks at `freq` Hz into the time that they take.
+    pub const fn ticks_to_duration(ticks: u64, freq: u32) -> Duration {
+        let freq = freq as u64;
+        // The remainder is less than `freq`, so this can't overflow.
+        let nanos = (ticks % freq) * 1_000_000_000 / freq;
+
+        Duration::new(ticks / freq, nanos as u32)
+    }
+
+    /// Must be called by the interrupt handler of the [`TimerSource`] on every tick,
+    /// with `ticks` being the new tick count.
+    pub fn on_tick(ticks: u64) {
+        if ticks % Self::freq() as u64 == 0 && HEARTBEAT.load(Ordering::Relaxed) {
+            crate::print!(".");
+        }
+        Thread::tick();
+    }
+}
+
+/// The default [`TimerSource`], channel 0 of the 8254 PIT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PitTimer;
+
+impl PitTimer {
+    fn on_interrupt(_vector: u8, _context: &mut InterruptContext) {
+        let now = Self::read_ticks() + 1;
+
+        let seq = TICKS_SEQ.load(Ordering::Relaxed);
+        TICKS_SEQ.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        TICKS_LO.store(now as u32, Ordering::Relaxed);
+        TICKS_HI.store((now >> 32) as u32, Ordering::Relaxed);
+        TICKS_SEQ.store(seq.wrapping_add(2), Ordering::Release);
+
+        Timer::on_tick(now);
+    }
+
+    /// Reads the tick count, see [`TICKS_SEQ`].
+    fn read_ticks() -> u64 {
+        loop {
+            let seq = TICKS_SEQ.load(Ordering::Acquire);
+            if seq % 2 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let lo = TICKS_LO.load(Ordering::Relaxed);
+            let hi = TICKS_HI.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if TICKS_SEQ.load(Ordering::Relaxed) == seq {
+                return ((hi as u64) << 32) | (lo as u64);
+            }
+        }
+    }
+}
+
+impl TimerSource for PitTimer {
+    fn name(&self) -> &'static str {
+        "8254 Timer"
+    }
+
+    fn init(&self, freq: u32) {
+        PIT::configure_channel(TimerChannel::Channel0, TimerMode::Mode2, freq);
+        Interrupts::register_handler(0x20, Self::on_interrupt, self.name());
+    }
+
+    fn ticks(&self) -> u64 {
+        Self::read_ticks()
+    }
+}
+
+/// Displays a [`Duration`] in a short human readable form like `1m23s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HumanDuration(pub Duration);
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let total = self.0.as_secs();
+        let (days, hours, minutes, seconds) = (total / 86400, total / 3600 % 24, total / 60 % 60, total % 60);
+
+        if days > 0 {
+            write!(f, "{days}d")?;
+        }
+        if days > 0 || hours > 0 {
+            write!(f, "{hours}h")?;
+        }
+        if days > 0 || hours > 0 || minutes > 0 {
+            write!(f, "{minutes}m")?;
+        }
+        write!(f, "{seconds}s")
     }
 }
 
@@ -59,14 +229,13 @@ impl PIT {
             ((Self::HZ + frequency / 2) / frequency) as u16
         };
 
-        let old_level = Interrupts::disable();
+        let _guard = Interrupts::disable_guarded();
         unsafe {
             CONTROL_PORT.lock().write((channel.value() << 6) | 0x30 | (mode.value() << 1));
             let mut counter_port = channel.port().lock();
             counter_port.write(count as u8);
             counter_port.write((count >> 8) as u8)
         }
-        Interrupts::set_level(old_level);
     }
 }
 
@@ -106,3 +275,42 @@ impl TimerMode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn ticks_to_duration() {
+        let convert = Timer::ticks_to_duration;
+
+        assert_eq!(convert(0, Timer::DEFAULT_FREQ), Duration::ZERO);
+        assert_eq!(convert(150, 100), Duration::from_millis(1500));
+        assert_eq!(convert(Timer::MIN_FREQ as u64 - 1, Timer::MIN_FREQ), Duration::new(0, 947_368_421));
+        assert_eq!(convert(1001, Timer::MAX_FREQ), Duration::from_millis(1001));
+
+        // 1 Hz and the fastest rate of the PIT, the remainder must not overflow for either.
+        assert_eq!(convert(u64::MAX, 1), Duration::from_secs(u64::MAX));
+        assert_eq!(convert(PIT::HZ as u64 + 1, PIT::HZ), Duration::new(1, 838));
+        assert_eq!(convert(u64::MAX, PIT::HZ), Duration::new(15_460_151_924_864, 271_622_890));
+    }
+
+    #[test]
+    fn human_duration() {
+        let human = |secs, nanos| HumanDuration(Duration::new(secs, nanos)).to_string();
+
+        assert_eq!(human(0, 0), "0s");
+        // Fractions of a second are cut off.
+        assert_eq!(human(1, 999_999_999), "1s");
+        assert_eq!(human(59, 0), "59s");
+        assert_eq!(human(60, 0), "1m0s");
+        assert_eq!(human(3599, 0), "59m59s");
+        assert_eq!(human(3600, 0), "1h0m0s");
+        assert_eq!(human(3601, 0), "1h0m1s");
+        assert_eq!(human(86399, 0), "23h59m59s");
+        assert_eq!(human(86400, 0), "1d0h0m0s");
+        assert_eq!(human(90061, 0), "1d1h1m1s");
+        assert_eq!(human(u64::MAX, 999_999_999), "213503982334601d7h0m15s");
+    }
+}
@@ -1,4 +1,43 @@
+use crate::threads::Interrupts;
+use crate::utils::backoff;
+use crate::utils::LineEditor;
+use crate::utils::LINE_LEN;
+#[cfg(test)]
+use alloc::string::String;
+use arrayvec::ArrayString;
+#[cfg(test)]
+use core::cell::RefCell;
 use core::fmt::Write;
+use lazy_static::lazy_static;
+use spinning_top::Spinlock;
+use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+/// The base port of the serial port used for the console.
+const COM1: u16 = 0x3F8;
+/// The base port of the serial port used for logging.
+const COM2: u16 = 0x2F8;
+
+lazy_static! {
+    static ref CONSOLE: Spinlock<SerialPort> = Spinlock::new(serial(COM1));
+    static ref LOG: Spinlock<SerialPort> = Spinlock::new(serial(COM2));
+}
+
+#[cfg(test)]
+std::thread_local! {
+    /// Collects everything printed in unit tests, which have no serial ports to print
+    /// to. Tests run in parallel, so each thread gets its own.
+    static CAPTURED: RefCell<String> = RefCell::new(String::new());
+}
+
+/// An implementation of the standard `print` macro that works in the kernel.
+/// Prints to the serial port by default.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {{
+        $crate::io::_print(format_args!($($arg)*));
+    }};
+}
 
 /// An implementation of the standard `println` macro that works in the kernel.
 /// Prints to the serial port by default.
@@ -12,11 +51,37 @@ macro_rules! println {
     }};
 }
 
-fn print_to<T>(args: core::fmt::Arguments<'_>, global_s: fn() -> T, label: &str)
-where
-    T: Write,
-{
-    if let Err(e) = global_s().write_fmt(args) {
+/// Like [`println`], but prints to the logging serial port (COM2) instead of the console.
+#[macro_export]
+macro_rules! log_println {
+    () => {
+        $crate::log_println!("");
+    };
+    ($($arg:tt)*) => {{
+        $crate::io::_log_print(format_args_nl!($($arg)*));
+    }};
+}
+
+/// Like [`println`], but marks the line as an error with an `[ERROR]` prefix so that
+/// it stands out in the boot log.
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprintln!("");
+    };
+    ($($arg:tt)*) => {{
+        $crate::io::_eprint(format_args_nl!($($arg)*));
+    }};
+}
+
+fn print_to(args: core::fmt::Arguments<'_>, port: &Spinlock<SerialPort>, label: &str) {
+    // Interrupt handlers may print as well, so they must not be able to interrupt
+    // us while we hold the lock.
+    let guard = Interrupts::disable_guarded();
+    let result = port.lock().write_fmt(args);
+    drop(guard);
+
+    if let Err(e) = result {
         panic!("failed printing to {label}: {e}");
     }
 }
@@ -24,12 +89,189 @@ where
 #[doc(hidden)]
 #[cfg(not(test))]
 pub fn _print(args: core::fmt::Arguments<'_>) {
-    print_to(args, serial, "serial");
+    print_to(args, &CONSOLE, "serial");
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: core::fmt::Arguments<'_>) {
+    _print(format_args!("[ERROR] {args}"));
+}
+
+#[doc(hidden)]
+#[cfg(not(test))]
+pub fn _log_print(args: core::fmt::Arguments<'_>) {
+    print_to(args, &LOG, "log serial");
+}
+
+#[doc(hidden)]
+#[cfg(test)]
+pub fn _print(args: core::fmt::Arguments<'_>) {
+    CAPTURED.with(|c| c.borrow_mut().write_fmt(args).expect("writing to a String can't fail"));
+}
+
+#[doc(hidden)]
+#[cfg(test)]
+pub fn _log_print(args: core::fmt::Arguments<'_>) {
+    _print(args);
+}
+
+/// Returns everything the current thread printed (including the log) since the last
+/// call and clears it.
+///
+/// Only available in unit tests, where printing doesn't need any hardware.
+#[cfg(test)]
+pub fn take_output() -> String {
+    CAPTURED.with(|c| c.take())
+}
+
+/// Writes `bytes` to the console serial port without locking or formatting anything.
+///
+/// This is only intended for when the kernel state cannot be trusted to work with
+/// [`println`] anymore, e.g. when panicking inside of the panic handler.
+pub fn raw_print(bytes: &[u8]) {
+    let mut data = Port::<u8>::new(COM1);
+    let mut line_status = Port::<u8>::new(COM1 + 5);
+
+    for &byte in bytes {
+        // SAFETY: at worst this interleaves with other output on the port.
+        unsafe {
+            // Wait until the transmit buffer is empty.
+            while line_status.read() & 0x20 == 0 {
+                core::hint::spin_loop();
+            }
+            data.write(byte);
+        }
+    }
+}
+
+/// Like [`raw_print`], but prints `n` as a decimal number, without formatting machinery.
+pub fn raw_print_u32(n: u32) {
+    raw_print(decimal(n, &mut [0; 10]));
+}
+
+/// Writes the decimal digits of `n` to the end of `buf` and returns them.
+fn decimal(mut n: u32, buf: &mut [u8; 10]) -> &[u8] {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buf[start..];
+        }
+    }
+}
+
+/// Waits for a byte to arrive on the console serial port and returns it.
+///
+/// The port is polled, since its receive interrupt isn't handled yet.
+// ToDo: read from an input queue filled by the interrupt handler instead
+pub fn read_byte() -> u8 {
+    let mut data = Port::<u8>::new(COM1);
+    let mut line_status = Port::<u8>::new(COM1 + 5);
+
+    // SAFETY: reading the received byte doesn't interfere with printing, which only
+    // writes to the transmit buffer.
+    unsafe {
+        // Wait until the data ready bit is set.
+        while line_status.read() & 0x01 == 0 {
+            backoff();
+        }
+        data.read()
+    }
+}
+
+/// Reads a line from the console serial port with `editor`, which echoes what is typed.
+pub fn read_line(editor: &mut LineEditor) -> ArrayString<LINE_LEN> {
+    crate::print!("{}", editor.prompt());
+
+    loop {
+        if let Some(line) = editor.feed(read_byte(), |s| crate::print!("{s}")) {
+            return line;
+        }
+    }
+}
+
+/// Checks that the console serial port works, see [`self_test`].
+pub fn console_self_test() -> bool {
+    // Nothing else may use the port while it is in loopback mode.
+    let _guard = Interrupts::disable_guarded();
+    let _console = CONSOLE.lock();
+
+    self_test(COM1)
+}
+
+/// Checks that the serial port at `base` works by sending a byte to itself in loopback
+/// mode. The previous modem control settings are restored afterwards.
+fn self_test(base: u16) -> bool {
+    const TEST_BYTE: u8 = 0xAE;
+    /// How often to check whether the byte arrived before giving up.
+    const POLLS: usize = 10_000;
+
+    let mut data = Port::<u8>::new(base);
+    let mut modem_control = Port::<u8>::new(base + 4);
+    let mut line_status = Port::<u8>::new(base + 5);
+
+    // SAFETY: the caller makes sure nobody else uses the port at the same time. Only
+    // the loopback bit is changed and it is reset before returning.
+    unsafe {
+        let old_modem_control = modem_control.read();
+        modem_control.write(old_modem_control | 0x10);
+
+        // Drop anything that was received before. A missing port reads as all ones, so
+        // this must not wait for the data ready bit to clear.
+        for _ in 0..16 {
+            if line_status.read() & 0x01 == 0 {
+                break;
+            }
+            data.read();
+        }
+
+        data.write(TEST_BYTE);
+        let mut received = None;
+        for _ in 0..POLLS {
+            if line_status.read() & 0x01 != 0 {
+                received = Some(data.read());
+                break;
+            }
+            core::hint::spin_loop();
+        }
+
+        modem_control.write(old_modem_control);
+        received == Some(TEST_BYTE)
+    }
 }
 
 /// Open a serial port for writing text to the output.
-fn serial() -> uart_16550::SerialPort {
-    let mut port = unsafe { uart_16550::SerialPort::new(0x3F8) };
+fn serial(base: u16) -> SerialPort {
+    let mut port = unsafe { SerialPort::new(base) };
     port.init();
     port
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_output() {
+        take_output();
+        crate::print!("a{}", 1);
+        crate::println!("b");
+        crate::log_println!("c");
+        crate::eprintln!("d");
+        assert_eq!(take_output(), "a1b\nc\n[ERROR] d\n");
+        assert_eq!(take_output(), "");
+    }
+
+    #[test]
+    fn decimal_digits() {
+        let digits = |n| String::from_utf8(decimal(n, &mut [0; 10]).to_vec()).unwrap();
+
+        assert_eq!(digits(0), "0");
+        assert_eq!(digits(7), "7");
+        assert_eq!(digits(10), "10");
+        assert_eq!(digits(1234), "1234");
+        assert_eq!(digits(u32::MAX), "4294967295");
+    }
+}
@@ -2,8 +2,8 @@
 //! - it is safe to have heap allocations in the kernel because `SimpleKernelAlloc` is
 //!   set as the global allocator for the entire crate.
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(format_args_nl)]
 #![feature(alloc_error_handler)]
 #![feature(panic_info_message)]
@@ -13,24 +13,46 @@
 
 extern crate alloc;
 
+use crate::boot::BootData;
+use crate::cmdline::KernelConfig;
+use crate::devices::HumanDuration;
 use crate::devices::Timer;
 use crate::mem::MemoryInfo;
 use crate::mem::PageAllocator;
 use crate::threads::Interrupts;
 use crate::threads::Thread;
+use crate::utils::LineEditor;
+use alloc::vec::Vec;
+use arrayvec::ArrayString;
 use bootloader_api::config::Mapping;
 use bootloader_api::BootloaderConfig;
 use core::arch::asm;
 use core::panic::PanicInfo;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
 
+mod boot;
+mod cmdline;
 mod devices;
 mod io;
 mod mem;
 mod proc;
+mod selftest;
+mod shutdown;
 mod threads;
 mod utils;
 
 fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    let config = KernelConfig::get();
+
+    if !io::console_self_test() {
+        log_println!("console serial port failed its self test");
+    }
+
+    BootData::init(boot_info);
+    let image = BootData::kernel_image_range();
+    log_println!("kernel image at {:#x}..{:#x}", image.start.raw(), image.end.raw());
+
     println!("Init Thread");
     Thread::init();
 
@@ -38,9 +60,10 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     println!("Init MemoryInfo");
     MemoryInfo::init(boot_info);
     println!("Init PageAllocator");
-    PageAllocator::init(u64::MAX);
+    PageAllocator::init(config.user_page_limit);
+    PageAllocator::reserve_boot_regions();
     println!("Init heap");
-    crate::mem::init_heap();
+    crate::mem::init_heap(config.heap_page_limit);
     // ToDo: paging_init();
 
     // Segmentation
@@ -51,11 +74,14 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     println!("Init Interrupts");
     Interrupts::init();
     println!("Init Timer");
-    Timer::init();
+    Timer::set_heartbeat(config.heartbeat);
+    Timer::init(config.timer_freq.unwrap_or(Timer::DEFAULT_FREQ));
     // ToDo: kbd_init();
     // ToDo: input_init();
     // ToDo: exception_init();
-    // ToDo: syscall_init();
+    proc::syscall_init();
+    #[cfg(debug_assertions)]
+    Interrupts::dump();
 
     // Start thread scheduler and enable interrupts
     // ToDo: thread_start();
@@ -66,14 +92,19 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     // ToDo: userprog_init();
 
     // Initialize file system
-    // ToDo: ide_init();
-    // ToDo: locate_block_devices();
-    // ToDo: filesys_init(format_filesys);
+    if config.no_filesys {
+        println!("Skipping file system initialization");
+    } else {
+        // ToDo: ide_init();
+        // ToDo: locate_block_devices();
+        // ToDo: skip the file system as well if there is no filesys device
+        // ToDo: filesys_init(config.format_filesys);
+    }
 
     println!("Boot complete.");
 
     // Run actions specified on kernel command line.
-    // ToDo: run_actions(argv);
+    run_actions(&config.actions);
 
     unsafe {
         x86_64::software_interrupt!(0x30);
@@ -83,39 +114,184 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     shutdown_power_off();
 }
 
+/// Runs the actions given on the kernel command line.
+fn run_actions(actions: &[&str]) {
+    let mut actions = actions.iter().copied();
+    while let Some(action) = actions.next() {
+        match action {
+            "-test" => {
+                // The outcome of the test must not be affected by the remaining actions,
+                // so shut down right after it.
+                match actions.next().map(|name| (name, selftest::run(name))) {
+                    Some((_, Some(true))) => shutdown_power_off(),
+                    Some((_, Some(false))) => shutdown_failure(),
+                    Some((name, None)) => {
+                        println!("unknown test '{name}', available tests:");
+                        selftest::names().for_each(|name| println!("  {name}"));
+                    }
+                    None => println!("missing test name for -test"),
+                }
+                shutdown_failure();
+            }
+            "-bench" => match actions.next() {
+                Some(name) if !selftest::bench::run(name) => {
+                    println!("unknown benchmark '{name}', available benchmarks:");
+                    selftest::bench::names().for_each(|name| println!("  {name}"));
+                }
+                Some(_) => {}
+                None => println!("missing benchmark name for -bench"),
+            },
+            "-test-alloc" => {
+                selftest::run("alloc");
+            }
+            "-memtest" => selftest::memtest::run(),
+            "uptime" => println!("up {}", HumanDuration(Timer::uptime())),
+            "profile" => {
+                let profile = Thread::scheduler_profile();
+                for thread in &profile.threads {
+                    println!("{:>8} ticks  {}", thread.samples, thread.name);
+                }
+                println!("{:>8} ticks  <unknown>", profile.unattributed);
+            }
+            "shell" => {
+                // Runs every typed line as a list of actions until `exit` is entered.
+                let mut editor = LineEditor::new("pintos> ");
+                loop {
+                    let line = io::read_line(&mut editor);
+                    let line: Vec<&str> = line.split_whitespace().collect();
+                    if line == ["exit"] {
+                        break;
+                    }
+                    run_actions(&line);
+                }
+            }
+            _ => println!("unknown action '{action}'"),
+        }
+    }
+}
+
+#[cfg(any(
+    all(feature = "fb-720", feature = "fb-1080"),
+    all(feature = "fb-720", feature = "no-fb"),
+    all(feature = "fb-1080", feature = "no-fb"),
+))]
+compile_error!("only one of the features `fb-720`, `fb-1080` and `no-fb` may be enabled");
+
+/// The minimum framebuffer height requested from the bootloader. Without any of the
+/// framebuffer features, this is 720 lines.
+///
+/// The bootloader always sets up a framebuffer, so `no-fb` only drops the requirement
+/// and lets it pick the smallest mode.
+const FRAMEBUFFER_HEIGHT: Option<u64> = if cfg!(feature = "no-fb") {
+    None
+} else if cfg!(feature = "fb-1080") {
+    Some(1080)
+} else {
+    Some(720)
+};
+
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
-    config.frame_buffer.minimum_framebuffer_height = Some(720);
+    config.frame_buffer.minimum_framebuffer_height = FRAMEBUFFER_HEIGHT;
     config.mappings.physical_memory = Some(Mapping::Dynamic);
     config
 };
+#[cfg(not(test))]
 bootloader_api::entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
+/// Set once the panic handler has been entered.
+static IN_PANIC: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    // ToDo: include thread name
+    if IN_PANIC.swap(true, Ordering::SeqCst) {
+        // The panic handler itself panicked, so don't touch anything that might have
+        // caused it (thread list, serial port locks, formatting) and stop right here.
+        io::raw_print(b"panicked while panicking");
+        if let Some(loc) = info.location() {
+            io::raw_print(b" at ");
+            io::raw_print(loc.file().as_bytes());
+            io::raw_print(b":");
+            io::raw_print_u32(loc.line());
+            io::raw_print(b":");
+            io::raw_print_u32(loc.column());
+        }
+        io::raw_print(b"\n");
+        halt();
+    }
+
+    // Copy the name instead of cloning it, the panic may have come from the heap.
+    let mut name = ArrayString::<32>::new();
+    match Thread::current() {
+        Some(thread) => thread.with_name(|n| {
+            // Longer names are cut off.
+            for c in n.chars() {
+                if name.try_push(c).is_err() {
+                    break;
+                }
+            }
+        }),
+        None => name.push_str("<unknown>"),
+    }
+
     if let Some(s) = info.message() {
         if let Some(loc) = info.location() {
-            println!("thread TODO panicked at '{s:?}', {}:{}", loc.file(), loc.line());
+            eprintln!("thread '{name}' panicked at '{s:?}', {}:{}:{}", loc.file(), loc.line(), loc.column());
         } else {
-            println!("thread TODO panicked at '{s:?}'");
+            eprintln!("thread '{name}' panicked at '{s:?}'");
         }
     } else {
-        println!("thread TODO panicked");
+        eprintln!("thread '{name}' panicked");
     }
 
-    shutdown_power_off();
+    #[cfg(feature = "hang-on-panic")]
+    halt();
+    #[cfg(not(feature = "hang-on-panic"))]
+    shutdown_failure();
 }
 
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!("allocation error: {:?}", layout)
 }
 
+/// Stops the CPU forever.
+pub fn halt() -> ! {
+    loop {
+        x86_64::instructions::interrupts::disable();
+        x86_64::instructions::hlt();
+    }
+}
+
 pub fn shutdown_power_off() -> ! {
+    power_off(EXIT_SUCCESS)
+}
+
+/// Like [`shutdown_power_off`], but makes QEMU report a failure, e.g. after a panic or
+/// a failed test.
+pub fn shutdown_failure() -> ! {
+    power_off(EXIT_FAILURE)
+}
+
+/// The values written to the port of QEMU's `isa-debug-exit` device, which then exits
+/// with the status `(value << 1) | 1`, i.e. 99 for success and 101 for failure.
+/// `scripts/run_x86_64.sh` maps these back to 0 and 1.
+const EXIT_SUCCESS: u32 = 0x31;
+const EXIT_FAILURE: u32 = 0x32;
+
+fn power_off(exit_code: u32) -> ! {
     use x86_64::instructions::{nop, port::Port};
 
+    shutdown::run_shutdown_hooks();
+
     unsafe {
+        // Exit code for newer QEMU versions. This comes first so that the exit code is
+        // not lost if one of the other methods below already turns off the machine.
+        let mut port = Port::new(0x501);
+        port.write(exit_code);
+
         let mut port = Port::new(0xB004);
         port.write(0x2000u16);
 
@@ -125,10 +301,6 @@ pub fn shutdown_power_off() -> ! {
         for i in 0..s.len() {
             port.write(s[i]);
         }
-
-        // Exit code for newer QEMU versions
-        let mut port = Port::new(0x501);
-        port.write(0x31u32);
     }
 
     loop {
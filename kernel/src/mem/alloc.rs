@@ -1,27 +1,165 @@
+#[cfg(not(test))]
 use crate::mem::PageAllocator;
 use crate::mem::VirtualAddress;
 use crate::mem::PAGE_SIZE;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::ptr::NonNull;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+#[cfg(not(test))]
 use enumflags2::BitFlags;
 use spinning_top::const_spinlock;
 use spinning_top::Spinlock;
-use tap::Pipe;
 use tap::Tap;
 use tap::TapOptional;
 
-const NUM_DESCS: usize = (PAGE_SIZE / 32).ilog2() as usize;
-
-#[global_allocator]
+/// The block size of the smallest descriptor.
+const MIN_BLOCK_SIZE: usize = 16;
+/// The block size of the largest descriptor, each descriptor has twice the block size
+/// of the previous one. Larger allocations get whole pages.
+///
+/// Can be raised up to 2048 bytes, the largest power of two that still leaves room
+/// for the arena header in a page. That doesn't save pages for allocations between 1 and
+/// 2 KB though: only one 2 KB block fits into an arena, and a big block takes one page as
+/// well (see `-bench alloc-medium`).
+const MAX_BLOCK_SIZE: usize = 1024;
+const NUM_DESCS: usize = (MAX_BLOCK_SIZE / MIN_BLOCK_SIZE).ilog2() as usize + 1;
+
+const _: () = assert!(MIN_BLOCK_SIZE.is_power_of_two() && MAX_BLOCK_SIZE.is_power_of_two());
+const _: () = assert!(MIN_BLOCK_SIZE >= core::mem::size_of::<Block>());
+const _: () = assert!(MAX_BLOCK_SIZE + core::mem::size_of::<Arena>() <= PAGE_SIZE as usize);
+
+/// Where the data of a big block starts in its first page, after the arena header. Big
+/// blocks can't be aligned to more than this.
+const BIG_BLOCK_OFFSET: usize = core::mem::size_of::<Arena>().next_power_of_two();
+
+#[cfg_attr(not(test), global_allocator)]
 static ALLOCATOR: SimpleKernelAlloc = SimpleKernelAlloc { descs: &ALLOC_DESCS };
 const DEFAULT_DESC: Spinlock<Descriptor> = const_spinlock(Descriptor::new());
 static ALLOC_DESCS: [Spinlock<Descriptor>; NUM_DESCS] = [DEFAULT_DESC; NUM_DESCS];
-
-pub fn init_heap() {
+/// The number of bytes currently handed out by the heap allocator.
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// The number of pages the heap allocator has taken from the kernel pool.
+static HEAP_PAGES: AtomicUsize = AtomicUsize::new(0);
+/// The maximum number of pages the heap allocator may take from the kernel pool.
+static HEAP_PAGE_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Set while the heap allocator runs, see [`ReentrancyGuard`].
+// ToDo: track this per thread once the scheduler can switch threads inside the allocator
+static IN_ALLOCATOR: AtomicBool = AtomicBool::new(false);
+
+/// Initializes the heap, which will not take more than `page_limit` pages from the
+/// kernel pool so that enough pages are left for e.g. thread stacks.
+pub fn init_heap(page_limit: usize) {
+    HEAP_PAGE_LIMIT.store(page_limit, Ordering::Relaxed);
     ALLOCATOR.init();
 }
 
+/// Returns the number of bytes that are currently allocated on the kernel heap.
+///
+/// This counts the requested sizes, not the size of the blocks backing them.
+pub fn heap_live_bytes() -> usize {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+/// The byte that free blocks are filled with in debug builds.
+#[cfg(debug_assertions)]
+const POISON: u8 = 0xCC;
+
+/// Panics if the free `block` of `block_size` bytes was written to since it was
+/// poisoned, which means that someone used it after freeing it.
+#[cfg(debug_assertions)]
+unsafe fn check_poison(block: NonNull<Block>, block_size: usize) {
+    // The start of the block holds the free list link.
+    let link_size = core::mem::size_of::<Block>();
+    let data = core::slice::from_raw_parts(block.cast::<u8>().as_ptr().add(link_size), block_size - link_size);
+
+    if data.iter().any(|&b| b != POISON) {
+        panic!("heap block at {:p} was modified after it was freed", block);
+    }
+}
+
+/// Returns the number of pages that the kernel heap currently occupies.
+pub fn heap_pages_used() -> usize {
+    HEAP_PAGES.load(Ordering::Relaxed)
+}
+
+/// Takes `num` pages from the kernel pool for the heap, unless that would exceed the
+/// heap page limit.
+fn get_heap_pages(num: usize) -> Option<NonNull<()>> {
+    let limit = HEAP_PAGE_LIMIT.load(Ordering::Relaxed);
+    HEAP_PAGES
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| used.checked_add(num).filter(|&n| n <= limit))
+        .ok()?;
+
+    let pages = take_pages(num);
+    if pages.is_none() {
+        HEAP_PAGES.fetch_sub(num, Ordering::Relaxed);
+    }
+
+    pages
+}
+
+/// Returns `num` pages taken with [`get_heap_pages`] to the kernel pool.
+fn free_heap_pages(pages: NonNull<()>, num: usize) {
+    release_pages(pages, num);
+    HEAP_PAGES.fetch_sub(num, Ordering::Relaxed);
+}
+
+#[cfg(not(test))]
+fn take_pages(num: usize) -> Option<NonNull<()>> {
+    PageAllocator::get_pages(BitFlags::empty(), num)
+}
+
+#[cfg(not(test))]
+fn release_pages(pages: NonNull<()>, num: usize) {
+    PageAllocator::free_pages(pages, num);
+}
+
+/// Unit tests have no page pools, so the pages come from the host instead.
+#[cfg(test)]
+fn take_pages(num: usize) -> Option<NonNull<()>> {
+    // SAFETY: the layout has a non-zero size.
+    NonNull::new(unsafe { std::alloc::alloc(host_page_layout(num)) }.cast())
+}
+
+#[cfg(test)]
+fn release_pages(pages: NonNull<()>, num: usize) {
+    // SAFETY: the pages were allocated by `take_pages` with the same layout.
+    unsafe { std::alloc::dealloc(pages.as_ptr().cast(), host_page_layout(num)) }
+}
+
+#[cfg(test)]
+fn host_page_layout(num: usize) -> Layout {
+    Layout::from_size_align(num * (PAGE_SIZE as usize), PAGE_SIZE as usize).unwrap()
+}
+
+/// Detects the heap allocator being entered again while it is already running, e.g.
+/// from an interrupt handler or from code it calls to get pages. That would deadlock on
+/// the descriptor locks, so it panics instead.
+struct ReentrancyGuard;
+
+impl ReentrancyGuard {
+    fn enter() -> Self {
+        if IN_ALLOCATOR.swap(true, Ordering::Acquire) {
+            // The panic handler takes the thread list lock to find the thread's name,
+            // which the interrupted allocation may hold (e.g. while adding a thread), so
+            // print the reason first in case the handler never gets to it.
+            crate::io::raw_print(b"heap allocator entered recursively\n");
+            panic!("heap allocator entered recursively");
+        }
+
+        Self
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        IN_ALLOCATOR.store(false, Ordering::Release);
+    }
+}
+
 /// A simple malloc implementation similar to the one used in the original Pintos.
 ///
 /// This allocator will allocate kernel-only memory and is not intended for use with
@@ -32,15 +170,39 @@ pub struct SimpleKernelAlloc {
 
 impl SimpleKernelAlloc {
     fn init(&self) {
-        let mut block_size = 16;
+        let mut block_size = MIN_BLOCK_SIZE;
         for desc in self.descs {
             let mut desc = desc.lock();
             desc.block_size = block_size;
-            desc.blocks_per_arena = ((PAGE_SIZE as usize) - core::mem::size_of::<Arena>()) / block_size;
+            // Arenas are page aligned, so this aligns every block to its size.
+            desc.first_block = core::mem::size_of::<Arena>().next_multiple_of(block_size);
+            desc.blocks_per_arena = ((PAGE_SIZE as usize) - desc.first_block) / block_size;
 
             block_size *= 2;
         }
     }
+
+    /// Returns the allocator that serves the kernel heap.
+    pub fn kernel_heap() -> &'static Self {
+        &ALLOCATOR
+    }
+
+    /// Runs `f` with a new allocator that gets its pages from the host.
+    ///
+    /// All allocators share some global state, e.g. [`IN_ALLOCATOR`], so only one test
+    /// may use one at a time.
+    #[cfg(test)]
+    pub fn with_test_heap<R>(f: impl FnOnce(&Self) -> R) -> R {
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let heap = Self {
+            descs: alloc::boxed::Box::leak(alloc::boxed::Box::new([DEFAULT_DESC; NUM_DESCS])),
+        };
+        heap.init();
+
+        f(&heap)
+    }
 }
 
 // ToDo: add safety notes to all unsafe function calls here
@@ -48,13 +210,17 @@ unsafe impl GlobalAlloc for SimpleKernelAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         // The safety requirements state that the caller must ensure that the layout
         // must have a non-zero size, so we do not need to check this.
+        let _guard = ReentrancyGuard::enter();
 
-        let desc = self.descs.iter().find(|d| d.lock().block_size >= layout.size());
+        // Blocks are aligned to their size, so both are covered by picking a large enough
+        // block size.
+        let min_block_size = layout.size().max(layout.align());
+        let desc = self.descs.iter().find(|d| d.lock().block_size >= min_block_size);
 
         if let Some(desc_raw) = desc {
             let mut desc = desc_raw.lock();
             if desc.free_list.is_empty() {
-                let Some(arena) = PageAllocator::get_pages(BitFlags::empty(), 1) else {
+                let Some(arena) = get_heap_pages(1) else {
                     return core::ptr::null_mut();
                 };
                 let arena = arena
@@ -68,22 +234,33 @@ unsafe impl GlobalAlloc for SimpleKernelAlloc {
                     .cast::<Arena>();
 
                 for i in 0..desc.blocks_per_arena {
-                    let block = arena.as_ref().to_block(i);
+                    let block = arena.as_ref().to_block(i, &desc);
+                    // Poison the new blocks like freed ones, so they pass the check below.
+                    #[cfg(debug_assertions)]
+                    core::ptr::write_bytes(block.cast::<u8>().as_ptr(), POISON, desc.block_size);
                     desc.free_list.push_back(block);
                 }
+                #[cfg(debug_assertions)]
+                assert_eq!(desc.free_list.len(), desc.blocks_per_arena);
             }
 
             let Some(block) = desc.free_list.pop_front() else {
                 return core::ptr::null_mut();
             };
+            #[cfg(debug_assertions)]
+            check_poison(block, desc.block_size);
             let mut arena = block.as_ref().to_arena();
             arena.as_mut().num_free -= 1;
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
             block.cast().as_ptr()
         } else {
             // The requested size is too big for any descriptor.
             // ALlocate enough pages to hold the size plus an arena
-            let num_pages = (layout.size() + core::mem::size_of::<Arena>()).div_ceil(PAGE_SIZE as usize);
-            let Some(arena) = PageAllocator::get_pages(BitFlags::empty(), num_pages) else {
+            if layout.align() > BIG_BLOCK_OFFSET {
+                return core::ptr::null_mut();
+            }
+            let num_pages = (layout.size() + BIG_BLOCK_OFFSET).div_ceil(PAGE_SIZE as usize);
+            let Some(arena) = get_heap_pages(num_pages) else {
                 return core::ptr::null_mut();
             };
             let arena = arena.tap(|a| unsafe {
@@ -93,22 +270,26 @@ unsafe impl GlobalAlloc for SimpleKernelAlloc {
                     num_free: num_pages,
                 }
             });
-            arena.cast().as_ptr()
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+            arena.cast::<u8>().as_ptr().add(BIG_BLOCK_OFFSET)
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         // SAFETY: the safety requirements state that `ptr` must not be null.
         let block = NonNull::new_unchecked(ptr).cast::<Block>();
+        let _guard = ReentrancyGuard::enter();
         let mut arena = block.as_ref().to_arena();
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
 
         if let Some(desc) = arena.as_ref().desc {
             // It's a normal block, handle it here.
 
             let mut desc = desc.lock();
+            arena.as_ref().check_block(block, &desc);
 
             #[cfg(debug_assertions)]
-            core::ptr::write_bytes(block.cast::<u8>().as_ptr(), 0xCC, desc.block_size);
+            core::ptr::write_bytes(block.cast::<u8>().as_ptr(), POISON, desc.block_size);
 
             desc.free_list.push_front(block);
 
@@ -116,13 +297,15 @@ unsafe impl GlobalAlloc for SimpleKernelAlloc {
             arena.as_mut().num_free += 1;
             if arena.as_mut().num_free >= desc.blocks_per_arena {
                 for i in 0..desc.blocks_per_arena {
-                    let block = arena.as_ref().to_block(i);
+                    let block = arena.as_ref().to_block(i, &desc);
                     desc.free_list.remove(block);
                 }
+                free_heap_pages(arena.cast(), 1);
             }
         } else {
             // It's a big block, free its pages.
-            PageAllocator::free_pages(arena.cast(), arena.as_ref().num_free);
+            let num_pages = arena.as_ref().num_free;
+            free_heap_pages(arena.cast(), num_pages);
         }
     }
 }
@@ -130,6 +313,8 @@ unsafe impl GlobalAlloc for SimpleKernelAlloc {
 #[derive(Debug)]
 struct Descriptor {
     block_size: usize,
+    /// The offset of the first block from the start of its arena.
+    first_block: usize,
     blocks_per_arena: usize,
     free_list: BlockList,
 }
@@ -138,6 +323,7 @@ impl Descriptor {
     const fn new() -> Self {
         Self {
             block_size: 0,
+            first_block: 0,
             blocks_per_arena: 0,
             free_list: BlockList::new(),
         }
@@ -158,15 +344,26 @@ struct Arena {
 impl Arena {
     pub const MAGIC: u32 = 0x9a548eed;
 
-    unsafe fn to_block(&self, idx: usize) -> NonNull<Block> {
-        let (blocks_per_arena, block_size) =
-            self.desc.map(|d| d.lock().pipe(|l| (l.blocks_per_arena, l.block_size))).unwrap_or((0, 0));
-
+    /// Returns the block `idx` of the arena, which belongs to `desc`.
+    ///
+    /// The caller already holds the lock of the descriptor, so its geometry is passed in
+    /// instead of locking it again, which would deadlock.
+    unsafe fn to_block(&self, idx: usize, desc: &Descriptor) -> NonNull<Block> {
         assert_eq!(self.magic, Self::MAGIC);
-        assert!(idx < blocks_per_arena);
+        assert!(idx < desc.blocks_per_arena);
         // SAFETY: this is save here, because it is relative to &self, which by definition
         // cannot be null.
-        NonNull::new_unchecked((self as *const Arena).add(1).cast::<u8>().cast_mut().add(idx * block_size)).cast()
+        NonNull::new_unchecked(
+            (self as *const Arena).cast::<u8>().cast_mut().add(desc.first_block + idx * desc.block_size),
+        )
+        .cast()
+    }
+
+    /// Checks that `block` starts at a block boundary of the arena, which belongs to `desc`.
+    fn check_block(&self, block: NonNull<Block>, desc: &Descriptor) {
+        let offset = VirtualAddress::new(block.as_ptr() as u64).page_offset() as usize;
+        assert!(offset >= desc.first_block);
+        assert_eq!((offset - desc.first_block) % desc.block_size, 0);
     }
 }
 
@@ -182,10 +379,26 @@ impl BlockList {
     }
 
     fn is_empty(&self) -> bool {
-        self.head == self.tail
+        self.head.is_none()
+    }
+
+    /// Returns the number of blocks in the list by walking it.
+    #[cfg(debug_assertions)]
+    unsafe fn len(&self) -> usize {
+        let mut len = 0;
+        let mut block = self.head;
+        while let Some(b) = block {
+            len += 1;
+            block = b.as_ref().next;
+        }
+
+        len
     }
 
     unsafe fn push_back(&mut self, block: NonNull<Block>) {
+        // The block may contain anything, e.g. the poison of a freed block.
+        (&mut *block.as_ptr()).next = None;
+
         if self.head.is_none() && self.tail.is_none() {
             self.head = Some(block);
             self.tail = Some(block);
@@ -197,6 +410,7 @@ impl BlockList {
 
     unsafe fn push_front(&mut self, block: NonNull<Block>) {
         if self.head.is_none() && self.tail.is_none() {
+            (&mut *block.as_ptr()).next = None;
             self.head = Some(block);
             self.tail = Some(block);
         } else {
@@ -244,6 +458,9 @@ impl BlockList {
             } else {
                 // Close the hole in the list
                 prev_v.as_mut().next = v.as_ref().next;
+                if self.tail == Some(v) {
+                    self.tail = Some(prev_v);
+                }
             }
         }
     }
@@ -264,15 +481,78 @@ impl Block {
 
         let a = arena.as_ref();
         assert_eq!(a.magic, Arena::MAGIC);
-        // SAFETY: the first OR condition validates that unwrap_unchecked in the second one is always valid.
-        assert!(
-            a.desc.is_none()
-                || ((addr.page_offset() as usize) - core::mem::size_of::<Arena>())
-                    % a.desc.unwrap_unchecked().lock().block_size
-                    == 0
-        );
-        assert!(a.desc.is_some() || (addr.page_offset() as usize) == core::mem::size_of::<Arena>());
+        // Blocks of normal arenas are checked with `Arena::check_block` once the
+        // descriptor is locked, the descriptor may already be locked here.
+        assert!(a.desc.is_some() || (addr.page_offset() as usize) == BIG_BLOCK_OFFSET);
 
         arena
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_list_is_empty() {
+        let mut block = Block { next: None };
+        let block = NonNull::from(&mut block);
+        let mut list = BlockList::new();
+        assert!(list.is_empty());
+
+        // SAFETY: the block outlives the list and is only accessed through it.
+        unsafe {
+            list.push_back(block);
+            assert!(!list.is_empty());
+            assert_eq!(list.pop_front(), Some(block));
+            assert!(list.is_empty());
+
+            list.push_front(block);
+            assert!(!list.is_empty());
+            list.remove(block);
+            assert!(list.is_empty());
+        }
+    }
+
+    #[test]
+    fn block_list_remove_tail() {
+        let mut blocks = [Block { next: None }, Block { next: None }, Block { next: None }];
+        let [a, b, c] = blocks.each_mut().map(NonNull::from);
+        let mut list = BlockList::new();
+
+        // SAFETY: the blocks outlive the list and are only accessed through it.
+        unsafe {
+            list.push_back(a);
+            list.push_back(b);
+            list.remove(b);
+            list.push_back(c);
+            assert_eq!(list.pop_front(), Some(a));
+            assert_eq!(list.pop_front(), Some(c));
+            assert_eq!(list.pop_front(), None);
+        }
+    }
+
+    #[test]
+    fn fresh_arenas() {
+        SimpleKernelAlloc::with_test_heap(|heap| {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let per_arena = heap.descs.iter().map(|d| d.lock()).find(|d| d.block_size == 64).unwrap().blocks_per_arena;
+            let pages_before = heap_pages_used();
+
+            // Filling more than one arena needs a fresh one while the descriptor is locked,
+            // and freeing everything empties the arenas again. Do both twice.
+            for _ in 0..2 {
+                let blocks: alloc::vec::Vec<_> = (0..=per_arena)
+                    // SAFETY: the layout has a non-zero size.
+                    .map(|_| unsafe { heap.alloc(layout) })
+                    .collect();
+                assert!(blocks.iter().all(|b| !b.is_null()));
+                for block in blocks {
+                    // SAFETY: the block was allocated above with the same layout.
+                    unsafe { heap.dealloc(block, layout) };
+                }
+                assert_eq!(heap_pages_used(), pages_before);
+            }
+        });
+    }
+}
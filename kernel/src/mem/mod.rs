@@ -8,6 +8,7 @@ use spinning_top::Spinlock;
 
 mod alloc;
 mod pages;
+pub mod paging;
 
 /// The index of the first offset bit.
 pub const PAGE_OFFSET_SHIFT: u32 = 0;
@@ -45,6 +46,25 @@ impl VirtualAddress {
         self.val >= (MEMORY_INFO.lock().base_virtual_address + PHYS_BASE)
     }
 
+    /// Checks that all `len` bytes starting at the address are user addresses and that the
+    /// range doesn't wrap around the end of the address space.
+    ///
+    /// This must be checked before accessing a buffer passed in by a user program, since a
+    /// buffer that starts in user space could still reach into the kernel.
+    pub fn validate_user_range(&self, len: u64) -> Result<(), ()> {
+        self.validate_user_range_in(len, &MemoryInfo::get())
+    }
+
+    /// Like [`VirtualAddress::validate_user_range`], but for the memory layout `info`.
+    fn validate_user_range_in(&self, len: u64, info: &MemoryInfo) -> Result<(), ()> {
+        let user_end = info.user_end();
+        // The end is exclusive, so the range may end right where the kernel starts.
+        match self.val.checked_add(len) {
+            Some(end) if self.val < user_end && end <= user_end => Ok(()),
+            _ => Err(()),
+        }
+    }
+
     pub fn to_kernel_physical(&self) -> PhysicalAddress {
         assert!(self.is_kernel());
 
@@ -116,7 +136,11 @@ pub struct MemoryInfo {
 
 impl MemoryInfo {
     /// Initializes the global memory info state.
-    pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
+    ///
+    /// # Panics
+    /// Panics if the bootloader did not map the physical memory, which all address
+    /// translations rely on.
+    pub fn init(boot_info: &bootloader_api::BootInfo) {
         let mut info = Self::get();
 
         for region in boot_info.memory_regions.deref() {
@@ -127,7 +151,13 @@ impl MemoryInfo {
             }
         }
 
-        info.base_virtual_address = boot_info.physical_memory_offset.into_option().unwrap_or(0);
+        info.base_virtual_address = match boot_info.physical_memory_offset.into_option() {
+            Some(offset) => offset,
+            None => panic!(
+                "the bootloader did not map the physical memory, \
+                 `BOOTLOADER_CONFIG` must request `Mapping::Dynamic` for it"
+            ),
+        };
 
         *MEMORY_INFO.lock() = info;
     }
@@ -143,4 +173,56 @@ impl MemoryInfo {
     pub fn num_pages(&self) -> u32 {
         (self.size / PAGE_SIZE) as u32
     }
+
+    /// Returns the first address that is not a user address.
+    pub fn user_end(&self) -> u64 {
+        self.base_virtual_address + PHYS_BASE
+    }
+
+    /// Returns the address that the mapping of all physical memory maps the absolute
+    /// physical address `phys` to, e.g. one returned by [`paging::lookup`].
+    ///
+    /// Unlike [`PhysicalAddress::to_kernel_virtual`], this doesn't add [`PHYS_BASE`].
+    pub fn mapped_virtual(&self, phys: PhysicalAddress) -> VirtualAddress {
+        VirtualAddress::new(self.base_virtual_address + phys.raw())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: MemoryInfo = MemoryInfo {
+        base_address: 0,
+        size: 0,
+        base_virtual_address: 0x1000_0000_0000,
+    };
+
+    fn validate(start: u64, len: u64) -> Result<(), ()> {
+        VirtualAddress::new(start).validate_user_range_in(len, &INFO)
+    }
+
+    #[test]
+    fn user_range_boundaries() {
+        let end = INFO.user_end();
+
+        assert_eq!(validate(0, 0), Ok(()));
+        assert_eq!(validate(0x1000, 0x1000), Ok(()));
+        // Ending right where the kernel starts is fine, one more byte is not.
+        assert_eq!(validate(end - 8, 8), Ok(()));
+        assert_eq!(validate(end - 8, 9), Err(()));
+        assert_eq!(validate(0, end), Ok(()));
+        // Straddling the boundary or starting in the kernel.
+        assert_eq!(validate(end - 4, 8), Err(()));
+        assert_eq!(validate(end, 0), Err(()));
+        assert_eq!(validate(end + 8, 8), Err(()));
+    }
+
+    #[test]
+    fn user_range_wraparound() {
+        assert_eq!(validate(0, u64::MAX), Err(()));
+        assert_eq!(validate(1, u64::MAX), Err(()));
+        assert_eq!(validate(u64::MAX, 1), Err(()));
+        assert_eq!(validate(u64::MAX - 7, 16), Err(()));
+    }
 }
@@ -1,9 +1,14 @@
+use crate::boot::BootData;
+use crate::mem::paging;
 use crate::mem::MemoryInfo;
 use crate::mem::PhysicalAddress;
 use crate::mem::VirtualAddress;
 use crate::mem::PAGE_SIZE;
 use crate::println;
+use crate::threads::Thread;
+use crate::utils::fast_zero;
 use crate::utils::BitSliceScan;
+use arrayvec::ArrayVec;
 use bitvec::slice::BitSlice;
 use core::ops::DerefMut;
 use core::ptr::NonNull;
@@ -19,6 +24,9 @@ static PAGE_ALLOC: PageAllocator = PageAllocator {
 
 type UsedMapType = usize;
 
+/// The number of recently freed single pages each pool remembers.
+const FREE_CACHE_LEN: usize = 32;
+
 #[bitflags]
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -47,7 +55,46 @@ impl PageAllocator {
         PAGE_ALLOC.user_pool.init(base, user_pages, "user pool");
     }
 
+    /// Marks the `num` pages starting at the page containing the absolute physical
+    /// address `start` as used, so that they are never handed out. Pages outside of the
+    /// pools are ignored.
+    pub fn reserve(start: PhysicalAddress, num: usize) {
+        let first = MemoryInfo::get().mapped_virtual(start);
+
+        for pool in [&PAGE_ALLOC.kernel_pool, &PAGE_ALLOC.user_pool] {
+            pool.reserve(first, num);
+        }
+    }
+
+    /// Reserves the memory regions that the bootloader reported to be in use by the
+    /// hardware, currently only the framebuffer.
+    pub fn reserve_boot_regions() {
+        let Some((start, info)) = BootData::framebuffer() else {
+            return;
+        };
+
+        let Some((phys, _)) = paging::lookup(paging::active_page_dir(), start) else {
+            return;
+        };
+        let offset = start.page_offset() as usize;
+        let num = (offset + info.byte_len).div_ceil(PAGE_SIZE as usize);
+
+        Self::reserve(phys, num);
+    }
+
     pub fn get_pages(flags: BitFlags<PageAllocFlags>, num: usize) -> Option<NonNull<()>> {
+        Self::get_pages_cached(flags, num, true)
+    }
+
+    /// Like [`PageAllocator::get_pages`], but always scans the bitmap from the start
+    /// instead of using the cache of freed single pages.
+    ///
+    /// Only intended for comparing both ways in benchmarks.
+    pub fn get_pages_uncached(flags: BitFlags<PageAllocFlags>, num: usize) -> Option<NonNull<()>> {
+        Self::get_pages_cached(flags, num, false)
+    }
+
+    fn get_pages_cached(flags: BitFlags<PageAllocFlags>, num: usize, use_cache: bool) -> Option<NonNull<()>> {
         if num == 0 {
             return None;
         }
@@ -61,21 +108,58 @@ impl PageAllocator {
             let mut used_map = pool.used_map.lock();
             let used_map = used_map.deref_mut().as_mut()?.0;
             // SAFETY: the used_map points to a static memory location (valid during the entire OS runtime).
-            unsafe { (&mut *used_map).scan_and_flip(0, num, false)? }
+            let used_map = unsafe { &mut *used_map };
+            let cached = match use_cache {
+                true => pool.take_cached_page(used_map, num),
+                false => None,
+            };
+            match cached {
+                Some(idx) => idx,
+                None => used_map.scan_and_flip(0, num, false)?,
+            }
         };
 
         let pages = pool.base.lock().raw() + PAGE_SIZE * (page_idx as u64);
 
         if flags.contains(PageAllocFlags::Zero) {
-            // ToDo: write safety statement
-            unsafe {
-                core::ptr::write_bytes(pages as *mut u8, 0, (PAGE_SIZE as usize) * num);
+            // Zeroing many pages takes a while, so let other threads run in between once
+            // there are any. Before that, zero everything at once.
+            let chunk_pages = match Thread::scheduler_started() {
+                true => 1,
+                false => num,
+            };
+            for chunk in (0..num).step_by(chunk_pages) {
+                let chunk_len = chunk_pages.min(num - chunk) * (PAGE_SIZE as usize);
+                // ToDo: write safety statement
+                unsafe {
+                    fast_zero((pages + PAGE_SIZE * (chunk as u64)) as *mut u8, chunk_len);
+                }
+                if chunk + chunk_pages < num {
+                    Thread::yield_now();
+                }
             }
         }
 
         Some(NonNull::new(pages as *mut ())?)
     }
 
+    /// Returns the number of pages of the kernel pool that are currently free.
+    pub fn free_kernel_pages() -> usize {
+        let used_map = PAGE_ALLOC.kernel_pool.used_map.lock();
+        // SAFETY: the used_map points to a static memory location (valid during the entire OS runtime).
+        used_map.map_or(0, |m| unsafe { &*m.0 }.count_zeros())
+    }
+
+    /// Returns a snapshot of which pages are currently in use, which can be compared to
+    /// a later one to detect leaked pages.
+    #[cfg(debug_assertions)]
+    pub fn usage_snapshot() -> PageUsage {
+        PageUsage {
+            kernel: PAGE_ALLOC.kernel_pool.usage(),
+            user: PAGE_ALLOC.user_pool.usage(),
+        }
+    }
+
     pub fn free_pages(pages: NonNull<()>, num: usize) {
         let page_addr = VirtualAddress::new(pages.as_ptr() as u64);
         assert_eq!(page_addr.page_offset(), 0);
@@ -105,6 +189,11 @@ impl PageAllocator {
             // SAFETY: the used_map points to a static memory location (valid during the entire OS runtime).
             let slice_range = (page_idx as usize)..((page_idx as usize) + num);
             unsafe { &mut *used_map.0 }.get_mut(slice_range).unwrap().fill(false);
+
+            if num == 1 {
+                // Nothing is lost if the cache is full, the page is still free in the bitmap.
+                let _ = pool.free_cache.lock().try_push(page_idx as usize);
+            }
         }
     }
 }
@@ -113,6 +202,9 @@ impl PageAllocator {
 struct Pool {
     used_map: Spinlock<Option<UsedMap>>,
     base: Spinlock<VirtualAddress>,
+    /// Indices of recently freed single pages, so that single page allocations don't
+    /// have to scan the bitmap. The bitmap stays the source of truth.
+    free_cache: Spinlock<ArrayVec<usize, FREE_CACHE_LEN>>,
 }
 
 impl Pool {
@@ -120,9 +212,29 @@ impl Pool {
         Self {
             used_map: const_spinlock(None),
             base: Spinlock::new(VirtualAddress::new(0)),
+            free_cache: const_spinlock(ArrayVec::new_const()),
         }
     }
 
+    /// Takes a free page from the cache of freed pages and marks it as used, if `num` is
+    /// a single page. Must be called while holding the lock of `used_map`.
+    fn take_cached_page(&self, used_map: &mut BitSlice<UsedMapType>, num: usize) -> Option<usize> {
+        if num != 1 {
+            return None;
+        }
+
+        let mut cache = self.free_cache.lock();
+        while let Some(idx) = cache.pop() {
+            // The page may have been handed out by a bitmap scan since it was cached.
+            if !used_map[idx] {
+                used_map.set(idx, true);
+                return Some(idx);
+            }
+        }
+
+        None
+    }
+
     fn init(&self, base: u64, num_pages: u64, name: &'static str) {
         let bitmap_pages = bitvec::mem::elts::<UsedMapType>(num_pages as usize).div_ceil(PAGE_SIZE as usize);
         if (bitmap_pages as u64) > num_pages {
@@ -132,11 +244,62 @@ impl Pool {
 
         println!("{num_pages} pages available in {name}");
 
-        let bitmat_slice = unsafe { core::slice::from_raw_parts_mut(base as *mut UsedMapType, 1) };
-        *self.used_map.lock() = Some(UsedMap(BitSlice::from_slice_mut(bitmat_slice) as *mut _));
+        // SAFETY: the bitmap pages belong to the pool and are never handed out.
+        let bitmap_slice = unsafe {
+            core::slice::from_raw_parts_mut(
+                base as *mut UsedMapType,
+                bitvec::mem::elts::<UsedMapType>(num_pages as usize),
+            )
+        };
+        let used_map = &mut BitSlice::from_slice_mut(bitmap_slice)[..(num_pages as usize)];
+        used_map.fill(false);
+        *self.used_map.lock() = Some(UsedMap(used_map as *mut _));
         *self.base.lock() = VirtualAddress::new(base + (bitmap_pages as u64) * PAGE_SIZE);
     }
 
+    /// Marks the `num` pages starting at the page containing `start` as used, as far as
+    /// they belong to the pool.
+    fn reserve(&self, start: VirtualAddress, num: usize) {
+        let first = start.page_round_down();
+
+        for i in 0..(num as u64) {
+            self.mark_used(VirtualAddress::new(first.raw() + i * PAGE_SIZE));
+        }
+    }
+
+    /// Marks `page` as used if it belongs to the pool.
+    fn mark_used(&self, page: VirtualAddress) {
+        if !self.contains_page(page) {
+            return;
+        }
+
+        let idx = (page.page_num() - self.base.lock().page_num()) as usize;
+        if let Some(used_map) = self.used_map.lock().as_mut() {
+            // SAFETY: the used_map points to a static memory location (valid during the entire OS runtime).
+            unsafe { &mut *used_map.0 }.set(idx, true);
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn usage(&self) -> PoolUsage {
+        let mut usage = PoolUsage {
+            used: 0,
+            hash: FNV_OFFSET,
+        };
+
+        if let Some(used_map) = self.used_map.lock().as_ref() {
+            // SAFETY: the used_map points to a static memory location (valid during the entire OS runtime).
+            for idx in unsafe { &*used_map.0 }.iter_ones() {
+                usage.used += 1;
+                for byte in idx.to_le_bytes() {
+                    usage.hash = (usage.hash ^ (byte as u64)).wrapping_mul(FNV_PRIME);
+                }
+            }
+        }
+
+        usage
+    }
+
     fn contains_page(&self, page: VirtualAddress) -> bool {
         let page_no = page.page_num();
         let start_page = self.base.lock().page_num();
@@ -146,8 +309,126 @@ impl Pool {
     }
 }
 
+/// Which pages of the pools are in use at some point, see
+/// [`PageAllocator::usage_snapshot`].
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageUsage {
+    pub kernel: PoolUsage,
+    pub user: PoolUsage,
+}
+
+/// The number of used pages in a pool and a hash of their indices.
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PoolUsage {
+    pub used: usize,
+    pub hash: u64,
+}
+
+#[cfg(debug_assertions)]
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+#[cfg(debug_assertions)]
+const FNV_PRIME: u64 = 0x100000001b3;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct UsedMap(*mut BitSlice<UsedMapType>);
 
 /// This is fine, because [`UsedMap`] only contains a pointer to static memory addresses.
 unsafe impl Send for UsedMap {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::alloc::Layout;
+
+    /// The number of pages of the test pools, more than fit into a single bitmap word.
+    const PAGES: u64 = 100;
+
+    /// Runs `f` with a pool of [`PAGES`] pages of host memory and a function returning
+    /// the address of each of these pages. The first page holds the bitmap.
+    fn with_pool(f: impl FnOnce(&Pool, &dyn Fn(u64) -> VirtualAddress)) {
+        let layout = Layout::from_size_align((PAGES * PAGE_SIZE) as usize, PAGE_SIZE as usize).unwrap();
+        // SAFETY: the layout has a non-zero size.
+        let base = unsafe { std::alloc::alloc(layout) } as u64;
+
+        let pool = Pool::new();
+        pool.init(base, PAGES, "test pool");
+        f(&pool, &|i| VirtualAddress::new(base + i * PAGE_SIZE));
+
+        // SAFETY: the pages were allocated above with the same layout.
+        unsafe { std::alloc::dealloc(base as *mut u8, layout) };
+    }
+
+    fn used_pages(pool: &Pool) -> alloc::vec::Vec<usize> {
+        let used_map = pool.used_map.lock().unwrap();
+        // SAFETY: the bitmap lives in the pages of `with_pool`.
+        unsafe { &*used_map.0 }.iter_ones().collect()
+    }
+
+    #[test]
+    fn pool_covers_all_pages() {
+        with_pool(|pool, page| {
+            assert_eq!(*pool.base.lock(), page(1));
+            assert!(!pool.contains_page(page(0)));
+            assert!(pool.contains_page(page(1)));
+            assert!(pool.contains_page(page(PAGES - 1)));
+            assert!(!pool.contains_page(page(PAGES)));
+
+            pool.mark_used(page(PAGES - 1));
+            assert_eq!(used_pages(pool), [(PAGES - 2) as usize]);
+        });
+    }
+
+    #[test]
+    fn reserve_overlapping_region() {
+        with_pool(|pool, page| {
+            // Physical memory is mapped such that the pages start at 1 MiB.
+            let info = MemoryInfo {
+                base_address: 0,
+                size: 0,
+                base_virtual_address: page(0).raw() - 0x10_0000,
+            };
+            let phys = |i: u64| PhysicalAddress::new_abs(0x10_0000 + i * PAGE_SIZE);
+            assert_eq!(info.mapped_virtual(phys(3)), page(3));
+
+            // From the middle of the bitmap page into the pool.
+            pool.reserve(info.mapped_virtual(PhysicalAddress::new_abs(phys(0).raw() + 0x800)), 3);
+            // From the last page past the end of the pool.
+            pool.reserve(info.mapped_virtual(phys(PAGES - 1)), 2);
+
+            assert_eq!(used_pages(pool), [0, 1, (PAGES - 2) as usize]);
+        });
+    }
+
+    /// Marks the page `idx` of `pool` as used or free, like allocating or freeing it.
+    fn set_used(pool: &Pool, idx: usize, used: bool) {
+        let used_map = pool.used_map.lock().unwrap();
+        // SAFETY: the bitmap lives in the pages of `with_pool`.
+        unsafe { &mut *used_map.0 }.set(idx, used);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn usage_tracks_used_pages() {
+        with_pool(|pool, _| {
+            let empty = pool.usage();
+            assert_eq!(empty.used, 0);
+
+            set_used(pool, 4, true);
+            let one = pool.usage();
+            assert_eq!(one.used, 1);
+            assert_ne!(one.hash, empty.hash);
+
+            // Another page in use instead doesn't look the same.
+            set_used(pool, 4, false);
+            set_used(pool, 5, true);
+            assert_eq!(pool.usage().used, 1);
+            assert_ne!(pool.usage(), one);
+
+            // Freeing it again restores the first snapshot.
+            set_used(pool, 5, false);
+            assert_eq!(pool.usage(), empty);
+        });
+    }
+}
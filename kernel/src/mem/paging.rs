@@ -0,0 +1,214 @@
+//! Access to the 4-level x86_64 page tables.
+//!
+//! The tables are reached through the mapping of all physical memory that the bootloader
+//! sets up at [`MemoryInfo::base_virtual_address`].
+
+use crate::mem::MemoryInfo;
+use crate::mem::PageAllocFlags;
+use crate::mem::PageAllocator;
+use crate::mem::PhysicalAddress;
+use crate::mem::VirtualAddress;
+use crate::mem::PAGE_SIZE;
+use crate::threads::Interrupts;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PageTable;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::PhysAddr;
+use x86_64::VirtAddr;
+
+/// The size of the virtual window that [`map_mmio`] maps regions into, which is the
+/// range covered by one top level entry.
+const MMIO_WINDOW_SIZE: u64 = 1 << 39;
+
+/// The next unused address in the MMIO window and the end of the window, or `None`
+/// before the window was chosen.
+static MMIO_WINDOW: Spinlock<Option<(u64, u64)>> = const_spinlock(None);
+
+/// Returns the physical address of the top level page table that is currently active.
+pub fn active_page_dir() -> PhysicalAddress {
+    PhysicalAddress::new_abs(Cr3::read().0.start_address().as_u64())
+}
+
+/// Looks up the mapping of `addr` in the page tables rooted at `pd` and returns the
+/// physical address it maps to together with the flags of the mapping, or `None` if
+/// the address is not mapped.
+///
+/// This only reads the tables and never faults, even if parts of them are not present.
+pub fn lookup(pd: PhysicalAddress, addr: VirtualAddress) -> Option<(PhysicalAddress, PageTableFlags)> {
+    let addr = x86_64::VirtAddr::try_new(addr.raw()).ok()?;
+    let indices = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    // The number of offset bits below the entries of each level.
+    let shifts = [39, 30, 21, 12];
+
+    let mut table = pd.raw();
+    for (level, (index, shift)) in indices.into_iter().zip(shifts).enumerate() {
+        // SAFETY: `table` is the physical address of a page table, either the root
+        // given by the caller or read from a present entry of its parent.
+        let entry = &unsafe { table_at(table) }[index];
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+
+        // 1 GiB and 2 MiB pages end the walk early, PML4 entries can't be huge.
+        let is_last = level == 3 || (level > 0 && flags.contains(PageTableFlags::HUGE_PAGE));
+        if is_last {
+            let offset = addr.as_u64() & ((1 << shift) - 1);
+            return Some((PhysicalAddress::new_abs(entry.addr().as_u64() + offset), flags));
+        }
+
+        table = entry.addr().as_u64();
+    }
+
+    unreachable!()
+}
+
+/// Copies `dst.len()` bytes from the user address `src` of the active address space.
+///
+/// Fails without copying anything unless all bytes are mapped for user programs.
+pub fn copy_from_user(src: VirtualAddress, dst: &mut [u8]) -> Result<(), ()> {
+    check_user_pages(src, dst.len() as u64, PageTableFlags::USER_ACCESSIBLE)?;
+    // SAFETY: all bytes of the source are mapped user memory, which never overlaps the
+    // kernel memory of `dst`.
+    unsafe { core::ptr::copy_nonoverlapping(src.raw() as *const u8, dst.as_mut_ptr(), dst.len()) };
+
+    Ok(())
+}
+
+/// Copies `src` to the user address `dst` of the active address space.
+///
+/// Fails without copying anything unless all bytes are mapped writable for user programs.
+pub fn copy_to_user(dst: VirtualAddress, src: &[u8]) -> Result<(), ()> {
+    check_user_pages(dst, src.len() as u64, PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE)?;
+    // SAFETY: all bytes of the destination are mapped writable user memory, which never
+    // overlaps the kernel memory of `src`.
+    unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), dst.raw() as *mut u8, src.len()) };
+
+    Ok(())
+}
+
+/// Checks that the `len` bytes at `addr` are user addresses and that all pages they touch
+/// are mapped into the active address space with `flags`.
+fn check_user_pages(addr: VirtualAddress, len: u64, flags: PageTableFlags) -> Result<(), ()> {
+    addr.validate_user_range(len)?;
+
+    let pd = active_page_dir();
+    let end = addr.raw() + len;
+    let mut page = addr.page_round_down().raw();
+    while page < end {
+        let (_, page_flags) = lookup(pd, VirtualAddress::new(page)).ok_or(())?;
+        if !page_flags.contains(flags) {
+            return Err(());
+        }
+        page += PAGE_SIZE;
+    }
+
+    Ok(())
+}
+
+/// Maps the `len` bytes of device memory at `phys` into the kernel's MMIO window with
+/// caching disabled and returns the virtual address that `phys` is mapped to.
+///
+/// Unlike [`PhysicalAddress::to_kernel_virtual`], this also works for addresses that are
+/// not covered by the mapping of physical memory, e.g. high PCI BARs. Mappings are never
+/// removed again.
+///
+/// # Panics
+/// Panics if the MMIO window is full, there is no free top level entry for it or a page
+/// table can't be allocated.
+pub fn map_mmio(phys: PhysicalAddress, len: u64) -> VirtualAddress {
+    let offset = phys.raw() % PAGE_SIZE;
+    let start = phys.raw() - offset;
+    let size = (offset + len).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+    // Interrupt handlers must not see half of a mapping.
+    let _guard = Interrupts::disable_guarded();
+    let mut window = MMIO_WINDOW.lock();
+    let pd = active_page_dir();
+    let (next, end) = window.get_or_insert_with(|| {
+        let base = free_kernel_slot(pd).expect("no free top level page table entry for the MMIO window");
+        (base, base + MMIO_WINDOW_SIZE)
+    });
+    if *end - *next < size {
+        panic!("the MMIO window is full, can't map {len} bytes at {:#x}", phys.raw());
+    }
+    let virt = *next;
+    *next += size;
+
+    let flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    for page in (0..size).step_by(PAGE_SIZE as usize) {
+        // SAFETY: the page lies in the MMIO window, which only this function maps into
+        // and which hands out every page once.
+        unsafe { map_page(pd, virt + page, start + page, flags) };
+    }
+
+    VirtualAddress::new(virt + offset)
+}
+
+/// Returns the start of the highest range in the kernel half of the address space whose
+/// top level entry is not present, or `None` if every entry is in use.
+fn free_kernel_slot(pd: PhysicalAddress) -> Option<u64> {
+    // SAFETY: `pd` is the active top level table.
+    let table = unsafe { table_at(pd.raw()) };
+    let index = (256..512).rev().find(|&index| table[index].is_unused())?;
+
+    // The kernel half starts at the sign extended address of entry 256.
+    Some(VirtAddr::new_truncate((index as u64) << 39).as_u64())
+}
+
+/// Maps the page at `virt` to the physical page at `phys` with `flags`, creating missing
+/// intermediate tables on the way.
+///
+/// # Safety
+/// `pd` must be the active top level table, interrupts must be disabled and `virt` must
+/// not be in use so that replacing its mapping can't break anything.
+unsafe fn map_page(pd: PhysicalAddress, virt: u64, phys: u64, flags: PageTableFlags) {
+    Interrupts::assert_disabled();
+
+    let addr = VirtAddr::new(virt);
+    let indices = [addr.p4_index(), addr.p3_index(), addr.p2_index()];
+
+    let mut table = pd.raw();
+    for index in indices {
+        // SAFETY: `table` is the root or was read from a present entry of its parent.
+        let entry = &mut unsafe { table_at_mut(table) }[index];
+        if entry.is_unused() {
+            let new = PageAllocator::get_pages(PageAllocFlags::Zero.into(), 1).expect("out of pages for page tables");
+            let (new_phys, _) = lookup(pd, VirtualAddress::new(new.as_ptr() as u64)).expect("page is not mapped");
+            entry.set_addr(PhysAddr::new(new_phys.raw()), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+        assert!(!entry.flags().contains(PageTableFlags::HUGE_PAGE), "{virt:#x} is inside a huge page");
+
+        table = entry.addr().as_u64();
+    }
+
+    // SAFETY: `table` was read from a present entry of the level above.
+    let entry = &mut unsafe { table_at_mut(table) }[addr.p1_index()];
+    entry.set_addr(PhysAddr::new(phys), flags);
+    tlb::flush(addr);
+}
+
+/// Returns the page table at the physical address `phys`.
+///
+/// # Safety
+/// `phys` must be the address of a page table.
+unsafe fn table_at(phys: u64) -> &'static PageTable {
+    let virt = MemoryInfo::get().base_virtual_address + phys;
+
+    unsafe { &*(virt as *const PageTable) }
+}
+
+/// Returns the page table at the physical address `phys` for modification.
+///
+/// # Safety
+/// `phys` must be the address of a page table and nobody else may access it until the
+/// returned reference is dropped.
+unsafe fn table_at_mut(phys: u64) -> &'static mut PageTable {
+    let virt = MemoryInfo::get().base_virtual_address + phys;
+
+    unsafe { &mut *(virt as *mut PageTable) }
+}
@@ -0,0 +1,191 @@
+//! Loading of ELF64 executables for user programs.
+//!
+//! Executables are untrusted input, so every problem with them is reported as an
+//! [`ElfError`] instead of a panic, allowing the process to fail cleanly.
+//!
+//! Only the kind of binary the user program toolchain produces is supported: statically
+//! linked, position dependent (`ET_EXEC`) little endian x86_64 executables. Everything
+//! else is rejected with a specific error before anything is loaded.
+
+use crate::devices::BlockDevice;
+use crate::devices::BlockError;
+use crate::devices::SectorIdx;
+use crate::devices::SECTOR_SIZE;
+use crate::mem::VirtualAddress;
+use crate::utils::le;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The size of the ELF64 file header.
+const EHDR_SIZE: usize = 64;
+/// The size of an ELF64 program header.
+const PHDR_SIZE: usize = 56;
+/// The maximum number of program headers that are accepted.
+const MAX_PHDRS: u16 = 128;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElfError {
+    /// The file does not start with the ELF magic number.
+    BadMagic,
+    /// The file is not a 64-bit ELF file.
+    UnsupportedClass,
+    /// The file is not little endian.
+    UnsupportedEndianness,
+    /// The file is not for x86_64.
+    UnsupportedMachine,
+    /// The file is a position independent executable or shared library (`ET_DYN`).
+    PositionIndependent,
+    /// The file is not an executable at all, e.g. an object file.
+    NotExecutable,
+    /// The executable needs a dynamic linker.
+    DynamicallyLinked,
+    /// The program headers don't have the size of ELF64 program headers.
+    BadProgramHeaderSize,
+    /// The file has more than [`MAX_PHDRS`] program headers.
+    TooManyProgramHeaders,
+    /// The program headers or a header field lie (partially) outside of the file.
+    HeaderOutOfRange,
+    /// A segment lies (partially) outside of the file or the address space.
+    SegmentOutOfRange,
+    /// Reading the file failed.
+    Read(BlockError),
+}
+
+impl From<BlockError> for ElfError {
+    fn from(e: BlockError) -> Self {
+        Self::Read(e)
+    }
+}
+
+/// The entry point of a loaded executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Entry(pub VirtualAddress);
+
+/// A loadable segment of an executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Segment {
+    offset: u64,
+    vaddr: u64,
+    file_size: u64,
+    mem_size: u64,
+}
+
+/// Loads the executable stored on `device` and returns its entry point.
+pub fn load_elf(device: &dyn BlockDevice) -> Result<Entry, ElfError> {
+    let file_size = (device.sector_count() as u64) * (SECTOR_SIZE as u64);
+
+    let mut ehdr = [0; EHDR_SIZE];
+    read_bytes(device, 0, &mut ehdr)?;
+
+    if ehdr[0..4] != *b"\x7fELF" {
+        return Err(ElfError::BadMagic);
+    }
+    if ehdr[4] != 2 {
+        return Err(ElfError::UnsupportedClass);
+    }
+    if ehdr[5] != 1 {
+        return Err(ElfError::UnsupportedEndianness);
+    }
+    if u16_at(&ehdr, 18)? != 62 {
+        return Err(ElfError::UnsupportedMachine);
+    }
+    match u16_at(&ehdr, 16)? {
+        ET_EXEC => {}
+        ET_DYN => return Err(ElfError::PositionIndependent),
+        _ => return Err(ElfError::NotExecutable),
+    }
+
+    let entry = u64_at(&ehdr, 24)?;
+    let phoff = u64_at(&ehdr, 32)?;
+    let phentsize = u16_at(&ehdr, 54)?;
+    let phnum = u16_at(&ehdr, 56)?;
+
+    if phnum > MAX_PHDRS {
+        return Err(ElfError::TooManyProgramHeaders);
+    }
+    if phnum > 0 && phentsize as usize != PHDR_SIZE {
+        return Err(ElfError::BadProgramHeaderSize);
+    }
+    let phdrs_size = (phnum as u64) * (PHDR_SIZE as u64);
+    if phoff.checked_add(phdrs_size).map_or(true, |end| end > file_size) {
+        return Err(ElfError::HeaderOutOfRange);
+    }
+
+    let mut phdrs = vec![0; phdrs_size as usize];
+    read_bytes(device, phoff, &mut phdrs)?;
+
+    // Check the shape of the whole file before anything is loaded.
+    for phdr in phdrs.chunks_exact(PHDR_SIZE) {
+        if matches!(u32_at(phdr, 0)?, PT_DYNAMIC | PT_INTERP) {
+            return Err(ElfError::DynamicallyLinked);
+        }
+    }
+
+    for phdr in phdrs.chunks_exact(PHDR_SIZE) {
+        // Other segments (notes, stack flags, ...) don't need to be loaded.
+        if u32_at(phdr, 0)? != PT_LOAD {
+            continue;
+        }
+
+        let segment = Segment {
+            offset: u64_at(phdr, 8)?,
+            vaddr: u64_at(phdr, 16)?,
+            file_size: u64_at(phdr, 32)?,
+            mem_size: u64_at(phdr, 40)?,
+        };
+        check_segment(&segment, file_size)?;
+        // ToDo: map the segment into the page directory of the process once paging exists
+    }
+
+    Ok(Entry(VirtualAddress::new(entry)))
+}
+
+/// Checks that `segment` lies within a file of `file_size` bytes and in user memory.
+fn check_segment(segment: &Segment, file_size: u64) -> Result<(), ElfError> {
+    let in_file = segment.offset.checked_add(segment.file_size).is_some_and(|end| end <= file_size);
+    let mem_end = segment.vaddr.checked_add(segment.mem_size);
+    let in_user_memory = mem_end.is_some_and(|end| end == 0 || VirtualAddress::new(end - 1).is_user());
+
+    if !in_file || !in_user_memory || segment.file_size > segment.mem_size {
+        return Err(ElfError::SegmentOutOfRange);
+    }
+
+    Ok(())
+}
+
+/// Reads `buf.len()` bytes starting at byte `offset` of `device` into `buf`.
+fn read_bytes(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> Result<(), ElfError> {
+    let first = offset / (SECTOR_SIZE as u64);
+    let last = (offset + buf.len() as u64).div_ceil(SECTOR_SIZE as u64);
+    let start: SectorIdx = first.try_into().map_err(|_| ElfError::SegmentOutOfRange)?;
+    let count: SectorIdx = (last - first).try_into().map_err(|_| ElfError::SegmentOutOfRange)?;
+
+    let mut sectors: Vec<u8> = vec![0; (count as usize) * SECTOR_SIZE];
+    device.read_range(start, count, &mut sectors)?;
+
+    let skip = (offset % (SECTOR_SIZE as u64)) as usize;
+    buf.copy_from_slice(&sectors[skip..(skip + buf.len())]);
+
+    Ok(())
+}
+
+// The headers are always read completely, but a field outside of them is still reported
+// as an error instead of panicking.
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, ElfError> {
+    le::read_u16(bytes, offset).ok_or(ElfError::HeaderOutOfRange)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, ElfError> {
+    le::read_u32(bytes, offset).ok_or(ElfError::HeaderOutOfRange)
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<u64, ElfError> {
+    le::read_u64(bytes, offset).ok_or(ElfError::HeaderOutOfRange)
+}
@@ -1,3 +1,7 @@
+pub use elf::*;
 pub use process::*;
+pub use syscall::*;
 
+mod elf;
 mod process;
+mod syscall;
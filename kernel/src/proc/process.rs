@@ -1,10 +1,97 @@
+use crate::mem::paging;
+use crate::mem::PhysicalAddress;
 use crate::threads::Thread;
 use alloc::string::String;
+use alloc::string::ToString;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+
+/// All processes that have been created and not removed yet.
+static ALL_PROCESSES: Spinlock<Vec<Arc<Process>>> = const_spinlock(Vec::new());
 
 #[derive(Debug)]
 pub struct Process {
-    page_dir: (), // ToDo,
+    pid: ProcessId,
+    /// The physical address of the top level page table of the process.
+    page_dir: PhysicalAddress,
     name: String,
     main_thread: Arc<Thread>,
+    // ToDo: open files, working directory and memory mappings once they exist
+}
+
+impl Process {
+    /// Creates a new process running `main_thread` and adds it to the process table.
+    pub fn new(name: impl ToString, main_thread: Arc<Thread>) -> Arc<Process> {
+        let process = Arc::new(Self {
+            pid: ProcessId::new(),
+            // ToDo: give every process its own page directory once user memory is isolated
+            page_dir: paging::active_page_dir(),
+            name: name.to_string(),
+            main_thread,
+        });
+        ALL_PROCESSES.lock().push(Arc::clone(&process));
+
+        process
+    }
+
+    /// Returns the process with the id `pid`, if it exists.
+    pub fn get(pid: ProcessId) -> Option<Arc<Process>> {
+        ALL_PROCESSES.lock().iter().find(|p| p.pid == pid).cloned()
+    }
+
+    /// Removes the process `pid` from the process table, e.g. once its exit status was
+    /// collected. Returns the process if it existed.
+    pub fn remove(pid: ProcessId) -> Option<Arc<Process>> {
+        let mut processes = ALL_PROCESSES.lock();
+        let idx = processes.iter().position(|p| p.pid == pid)?;
+
+        Some(processes.swap_remove(idx))
+    }
+
+    /// Returns the process the current thread belongs to, if it is a user program.
+    pub fn current() -> Option<Arc<Process>> {
+        Thread::current()?.process().cloned()
+    }
+
+    /// Returns the id of the process.
+    pub fn pid(&self) -> ProcessId {
+        self.pid
+    }
+
+    /// Returns the name of the process.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the thread that runs the process.
+    pub fn main_thread(&self) -> &Arc<Thread> {
+        &self.main_thread
+    }
+
+    /// Returns the physical address of the top level page table of the process.
+    pub fn page_dir(&self) -> PhysicalAddress {
+        self.page_dir
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProcessId(u32);
+
+impl ProcessId {
+    /// Allocates a new [`ProcessId`] by reading and incrementing
+    /// the global ID counter.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the raw id, as passed to user programs.
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
 }
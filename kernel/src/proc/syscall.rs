@@ -0,0 +1,57 @@
+//! The system call interface for user programs.
+//!
+//! User programs invoke system calls with `int 0x30`, after pushing the arguments and
+//! then the system call number onto their stack.
+
+use crate::mem::paging;
+use crate::mem::VirtualAddress;
+use crate::println;
+use crate::threads::GateType;
+use crate::threads::InterruptContext;
+use crate::threads::Interrupts;
+use x86_64::PrivilegeLevel;
+
+/// The interrupt vector used for system calls.
+const SYSCALL_VECTOR: u8 = 0x30;
+
+/// Powers off the machine.
+pub const SYS_HALT: u64 = 0;
+// ToDo: SYS_EXEC must only return once the child reported whether `load_elf` succeeded
+// (e.g. through a semaphore the child ups after loading) and return -1 on failure.
+// ToDo: add SYS_GETPID (20) and the other system calls with results once the interrupt
+// entry saves the user registers, so that the result can be returned in rax.
+
+/// Registers the system call handler, which can be invoked from user mode.
+pub fn syscall_init() {
+    Interrupts::register_gate_handler(
+        SYSCALL_VECTOR,
+        GateType::Interrupt,
+        PrivilegeLevel::Ring3,
+        syscall_handler,
+        "syscall",
+    );
+}
+
+fn syscall_handler(_vector: u8, context: &mut InterruptContext) {
+    let Some(number) = read_user_u64(context.frame().stack_pointer.as_u64()) else {
+        // ToDo: terminate the calling process
+        println!("system call with invalid stack pointer {:?}", context.frame().stack_pointer);
+        return;
+    };
+
+    match number {
+        SYS_HALT => crate::shutdown_power_off(),
+        _ => {
+            // ToDo: terminate the calling process
+            println!("unknown system call {number}");
+        }
+    }
+}
+
+/// Reads a `u64` from the user address `addr`, if it is mapped for user programs.
+fn read_user_u64(addr: u64) -> Option<u64> {
+    let mut bytes = [0; 8];
+    paging::copy_from_user(VirtualAddress::new(addr), &mut bytes).ok()?;
+
+    Some(u64::from_le_bytes(bytes))
+}
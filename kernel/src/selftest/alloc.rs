@@ -0,0 +1,82 @@
+//! Stress test for the kernel heap allocator.
+//!
+//! Allocates many blocks of varied sizes and alignments, fills each with a pattern,
+//! and frees them again in random order, checking that no block was corrupted in
+//! the meantime and that the heap returns to its previous usage.
+
+use crate::mem::heap_live_bytes;
+use crate::mem::SimpleKernelAlloc;
+use crate::utils::Random;
+use alloc::alloc::GlobalAlloc;
+use alloc::alloc::Layout;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const NUM_BLOCKS: usize = 256;
+/// Large enough to also exercise allocations that span multiple pages.
+const MAX_SIZE: u64 = 6000;
+/// The largest alignment that multi-page blocks support.
+const MAX_ALIGN_SHIFT: u64 = 5;
+const SEED: u64 = 0x5eed_a110c;
+
+pub fn test() -> Result<(), String> {
+    stress(SimpleKernelAlloc::kernel_heap())
+}
+
+fn stress(heap: &dyn GlobalAlloc) -> Result<(), String> {
+    let mut rng = Random::new(SEED);
+    // Allocate the bookkeeping up front so it doesn't show up in the live bytes.
+    let mut blocks: Vec<(*mut u8, Layout, u8)> = Vec::with_capacity(NUM_BLOCKS);
+    let baseline = heap_live_bytes();
+
+    for i in 0..NUM_BLOCKS {
+        let size = 1 + rng.next_below(MAX_SIZE) as usize;
+        let align = 1 << rng.next_below(MAX_ALIGN_SHIFT + 1);
+        let layout = Layout::from_size_align(size, align).unwrap();
+
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { heap.alloc(layout) };
+        if ptr.is_null() {
+            return Err(format!("allocation {i} of {size} bytes failed"));
+        }
+        if (ptr as usize) % align != 0 {
+            return Err(format!("block {ptr:p} is not aligned to {align} bytes"));
+        }
+
+        let pattern = (i as u8) ^ 0xa5;
+        // SAFETY: the block was just allocated with `size` bytes.
+        unsafe { core::ptr::write_bytes(ptr, pattern, size) };
+        blocks.push((ptr, layout, pattern));
+    }
+
+    rng.shuffle(&mut blocks);
+
+    for &(ptr, layout, pattern) in &blocks {
+        // SAFETY: the block is still allocated and was fully initialized above.
+        let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+        if let Some(offset) = contents.iter().position(|&b| b != pattern) {
+            return Err(format!("block {ptr:p} was corrupted at offset {offset}"));
+        }
+
+        // SAFETY: the block was allocated with this layout and is freed only once.
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    let live = heap_live_bytes();
+    if live != baseline {
+        return Err(format!("{live} bytes live after freeing everything, expected {baseline}"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stress_test_heap() {
+        SimpleKernelAlloc::with_test_heap(|heap| assert_eq!(stress(heap), Ok(())));
+    }
+}
@@ -0,0 +1,169 @@
+//! Benchmarks that are run as command line actions.
+//!
+//! Every benchmark in [`BENCHES`] can be run by name with `-bench <name>`. Times are
+//! measured with the TSC, so they are in CPU cycles and only comparable on the same
+//! machine.
+
+use crate::mem::heap_pages_used;
+use crate::mem::PageAllocFlags;
+use crate::mem::PageAllocator;
+use crate::mem::SimpleKernelAlloc;
+use crate::mem::PAGE_SIZE;
+use crate::println;
+use crate::utils::fast_copy;
+use crate::utils::fast_zero;
+use alloc::alloc::GlobalAlloc;
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use enumflags2::BitFlags;
+
+/// How often each variant runs, the fastest run is reported.
+const ROUNDS: usize = 8;
+
+struct Bench {
+    name: &'static str,
+    func: fn(),
+}
+
+const BENCHES: &[Bench] = &[
+    Bench {
+        name: "memops",
+        func: memops,
+    },
+    Bench {
+        name: "alloc-medium",
+        func: alloc_medium,
+    },
+    Bench {
+        name: "single-page",
+        func: single_page,
+    },
+];
+
+/// Runs the benchmark `name` and prints its results.
+/// Returns `false` if there is no benchmark with that name.
+pub fn run(name: &str) -> bool {
+    let Some(bench) = BENCHES.iter().find(|bench| bench.name == name) else {
+        return false;
+    };
+
+    (bench.func)();
+    true
+}
+
+/// Returns the names of all benchmarks that can be passed to [`run`].
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BENCHES.iter().map(|bench| bench.name)
+}
+
+/// Returns the number of cycles of the fastest of [`ROUNDS`] runs of `f`.
+fn cycles(mut f: impl FnMut()) -> u64 {
+    (0..ROUNDS)
+        .map(|_| {
+            // SAFETY: reading the TSC has no side effects.
+            let start = unsafe { core::arch::x86_64::_rdtsc() };
+            f();
+            let end = unsafe { core::arch::x86_64::_rdtsc() };
+            end - start
+        })
+        .min()
+        .unwrap()
+}
+
+/// Compares [`fast_zero`] and [`fast_copy`] with the `core::ptr` functions they replace
+/// on 256 pages, the way [`PageAllocator::get_pages`] zeroes them.
+fn memops() {
+    const PAGES: usize = 256;
+    const LEN: usize = PAGES * (PAGE_SIZE as usize);
+
+    let (Some(dst), Some(src)) = (
+        PageAllocator::get_pages(BitFlags::empty(), PAGES),
+        PageAllocator::get_pages(BitFlags::empty(), PAGES),
+    ) else {
+        println!("bench-memops: not enough free pages");
+        return;
+    };
+    let (dst, src) = (dst.cast::<u8>().as_ptr(), src.cast::<u8>().as_ptr());
+
+    // SAFETY: both ranges are the `LEN` bytes of separate allocations.
+    let (write_bytes, zero, copy, copy_fast) = unsafe {
+        (
+            cycles(|| core::ptr::write_bytes(core::hint::black_box(dst), 0, LEN)),
+            cycles(|| fast_zero(core::hint::black_box(dst), LEN)),
+            cycles(|| core::ptr::copy_nonoverlapping(src, core::hint::black_box(dst), LEN)),
+            cycles(|| fast_copy(core::hint::black_box(dst), src, LEN)),
+        )
+    };
+    println!("bench-memops: zeroing {PAGES} pages: write_bytes {write_bytes} cycles, fast_zero {zero} cycles");
+    println!("bench-memops: copying {PAGES} pages: copy_nonoverlapping {copy} cycles, fast_copy {copy_fast} cycles");
+
+    PageAllocator::free_pages(NonNull::new(dst).unwrap().cast(), PAGES);
+    PageAllocator::free_pages(NonNull::new(src).unwrap().cast(), PAGES);
+}
+
+/// Allocates [`MEDIUM_ALLOCS`] blocks of 1.5 KB on the kernel heap, which are too big for
+/// descriptors of up to 1 KB blocks, and reports how many heap pages they took.
+fn alloc_medium() {
+    let (allocs, pages, cycles) = medium_allocs(SimpleKernelAlloc::kernel_heap());
+    println!("bench-alloc-medium: {allocs} allocations of 1.5 KB took {pages} heap pages and {cycles} cycles");
+}
+
+const MEDIUM_ALLOCS: usize = 64;
+
+/// Returns how many of the allocations of [`alloc_medium`] succeeded on `heap`, how many
+/// heap pages they took and how many cycles allocating them took.
+fn medium_allocs(heap: &dyn GlobalAlloc) -> (usize, usize, u64) {
+    let layout = Layout::from_size_align(1536, 8).unwrap();
+    let mut blocks = Vec::with_capacity(MEDIUM_ALLOCS);
+    let pages_before = heap_pages_used();
+
+    // SAFETY: reading the TSC has no side effects.
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+    for _ in 0..MEDIUM_ALLOCS {
+        // SAFETY: the layout has a non-zero size.
+        blocks.push(unsafe { heap.alloc(layout) });
+    }
+    let end = unsafe { core::arch::x86_64::_rdtsc() };
+    let pages = heap_pages_used() - pages_before;
+
+    blocks.retain(|b| !b.is_null());
+    let allocs = blocks.len();
+    for block in blocks {
+        // SAFETY: the block was allocated above with the same layout.
+        unsafe { heap.dealloc(block, layout) };
+    }
+
+    (allocs, pages, end - start)
+}
+
+/// Allocates and frees a single page [`SINGLE_PAGE_CYCLES`] times, once through the cache
+/// of freed pages and once scanning the bitmap from the start, while the first
+/// [`HELD_PAGES`] pages of the pool are in use.
+fn single_page() {
+    const SINGLE_PAGE_CYCLES: usize = 10_000;
+    /// The pages the scan has to skip, like the ones of long-lived allocations.
+    const HELD_PAGES: usize = 1024;
+
+    let Some(held) = PageAllocator::get_pages(BitFlags::empty(), HELD_PAGES) else {
+        println!("bench-single-page: not enough free pages");
+        return;
+    };
+
+    let run = |get: fn(BitFlags<PageAllocFlags>, usize) -> Option<NonNull<()>>| {
+        cycles(|| {
+            for _ in 0..SINGLE_PAGE_CYCLES {
+                let page = get(BitFlags::empty(), 1).expect("the page was freed before");
+                PageAllocator::free_pages(core::hint::black_box(page), 1);
+            }
+        })
+    };
+    let cached = run(PageAllocator::get_pages);
+    let scanning = run(PageAllocator::get_pages_uncached);
+    println!(
+        "bench-single-page: {SINGLE_PAGE_CYCLES} allocations behind {HELD_PAGES} used pages: \
+         cached {cached} cycles, scanning {scanning} cycles"
+    );
+
+    PageAllocator::free_pages(held, HELD_PAGES);
+}
@@ -0,0 +1,44 @@
+//! Round-trip test for block devices, using a [`RamDisk`] until there is a filesystem.
+
+use crate::devices::BlockDevice;
+use crate::devices::BlockError;
+use crate::devices::RamDisk;
+use crate::devices::SECTOR_SIZE;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+
+const SECTORS: u32 = 8;
+
+/// Writes a distinct pattern to every sector, both one at a time and as a range, and
+/// checks that it reads back unchanged and that accesses past the end are rejected.
+pub fn test() -> Result<(), String> {
+    let disk = RamDisk::new(SECTORS);
+    let pattern = |sector: u32, offset: usize| (sector as usize * 31 + offset) as u8;
+
+    for sector in 0..SECTORS / 2 {
+        let buf = core::array::from_fn(|offset| pattern(sector, offset));
+        disk.write(sector, &buf).map_err(|e| format!("writing sector {sector}: {e:?}"))?;
+    }
+    let mut range = vec![0; (SECTORS / 2) as usize * SECTOR_SIZE];
+    for (i, byte) in range.iter_mut().enumerate() {
+        *byte = pattern(SECTORS / 2 + (i / SECTOR_SIZE) as u32, i % SECTOR_SIZE);
+    }
+    disk.write_range(SECTORS / 2, SECTORS / 2, &range)
+        .map_err(|e| format!("writing the second half: {e:?}"))?;
+
+    let mut all = vec![0; SECTORS as usize * SECTOR_SIZE];
+    disk.read_range(0, SECTORS, &mut all).map_err(|e| format!("reading everything: {e:?}"))?;
+    for (i, &byte) in all.iter().enumerate() {
+        let (sector, offset) = ((i / SECTOR_SIZE) as u32, i % SECTOR_SIZE);
+        if byte != pattern(sector, offset) {
+            return Err(format!("sector {sector} differs at offset {offset}"));
+        }
+    }
+
+    let mut buf = [0; SECTOR_SIZE];
+    match disk.read(SECTORS, &mut buf) {
+        Err(BlockError::OutOfRange) => Ok(()),
+        result => Err(format!("reading past the end returned {result:?}")),
+    }
+}
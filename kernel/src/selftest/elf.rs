@@ -0,0 +1,188 @@
+//! Checks that [`load_elf`] accepts a minimal executable and rejects broken variants of
+//! it with the right [`ElfError`], reading them from a [`RamDisk`].
+
+use crate::devices::BlockError;
+use crate::devices::RamDisk;
+use crate::devices::SECTOR_SIZE;
+use crate::mem::MemoryInfo;
+use crate::mem::VirtualAddress;
+use crate::proc::load_elf;
+use crate::proc::ElfError;
+use crate::proc::Entry;
+use crate::utils::le;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const ENTRY: u64 = 0x40_1000;
+/// The offset of the only program header, right after the file header.
+const PHDR: usize = 64;
+
+/// A way to break the test executable and the error it must be rejected with.
+struct Case {
+    name: &'static str,
+    corrupt: fn(&mut Vec<u8>),
+    expected: ElfError,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "bad magic",
+        corrupt: |image| image[1] = b'X',
+        expected: ElfError::BadMagic,
+    },
+    Case {
+        name: "32-bit",
+        corrupt: |image| image[4] = 1,
+        expected: ElfError::UnsupportedClass,
+    },
+    Case {
+        name: "big endian",
+        corrupt: |image| image[5] = 2,
+        expected: ElfError::UnsupportedEndianness,
+    },
+    Case {
+        name: "wrong machine",
+        corrupt: |image| set_u16(image, 18, 3),
+        expected: ElfError::UnsupportedMachine,
+    },
+    Case {
+        name: "object file",
+        corrupt: |image| set_u16(image, 16, 1),
+        expected: ElfError::NotExecutable,
+    },
+    Case {
+        name: "position independent",
+        corrupt: |image| set_u16(image, 16, 3),
+        expected: ElfError::PositionIndependent,
+    },
+    Case {
+        name: "interpreter",
+        corrupt: |image| set_u32(image, PHDR, 3),
+        expected: ElfError::DynamicallyLinked,
+    },
+    Case {
+        name: "interpreter after a segment",
+        corrupt: |image| {
+            set_u16(image, 56, 2);
+            set_u32(image, PHDR + 56, 3);
+        },
+        expected: ElfError::DynamicallyLinked,
+    },
+    Case {
+        name: "dynamic section",
+        corrupt: |image| set_u32(image, PHDR, 2),
+        expected: ElfError::DynamicallyLinked,
+    },
+    Case {
+        name: "program header size",
+        corrupt: |image| set_u16(image, 54, 32),
+        expected: ElfError::BadProgramHeaderSize,
+    },
+    Case {
+        name: "program header count",
+        corrupt: |image| set_u16(image, 56, u16::MAX),
+        expected: ElfError::TooManyProgramHeaders,
+    },
+    Case {
+        name: "program headers past the end",
+        corrupt: |image| set_u64(image, 32, 2 * SECTOR_SIZE as u64),
+        expected: ElfError::HeaderOutOfRange,
+    },
+    Case {
+        name: "program header offset overflow",
+        corrupt: |image| set_u64(image, 32, u64::MAX - 8),
+        expected: ElfError::HeaderOutOfRange,
+    },
+    Case {
+        name: "truncated",
+        corrupt: |image| image.truncate(SECTOR_SIZE),
+        expected: ElfError::SegmentOutOfRange,
+    },
+    Case {
+        name: "empty",
+        corrupt: |image| image.clear(),
+        expected: ElfError::Read(BlockError::OutOfRange),
+    },
+    Case {
+        name: "file size above memory size",
+        corrupt: |image| set_u64(image, PHDR + 40, 0x100),
+        expected: ElfError::SegmentOutOfRange,
+    },
+    Case {
+        name: "segment in kernel memory",
+        corrupt: |image| set_u64(image, PHDR + 16, MemoryInfo::get().user_end() - 0x1000),
+        expected: ElfError::SegmentOutOfRange,
+    },
+    Case {
+        name: "segment address overflow",
+        corrupt: |image| set_u64(image, PHDR + 16, u64::MAX - 0xfff),
+        expected: ElfError::SegmentOutOfRange,
+    },
+];
+
+/// Returns an executable with a single segment that spans both of its sectors.
+fn image() -> Vec<u8> {
+    let mut image = vec![0; 2 * SECTOR_SIZE];
+
+    image[0..4].copy_from_slice(b"\x7fELF");
+    // 64-bit, little endian, version 1.
+    image[4..7].copy_from_slice(&[2, 1, 1]);
+    set_u16(&mut image, 16, 2);
+    set_u16(&mut image, 18, 62);
+    set_u64(&mut image, 24, ENTRY);
+    set_u64(&mut image, 32, PHDR as u64);
+    set_u16(&mut image, 52, 64);
+    set_u16(&mut image, 54, 56);
+    set_u16(&mut image, 56, 1);
+
+    set_u32(&mut image, PHDR, 1);
+    set_u64(&mut image, PHDR + 8, 0);
+    set_u64(&mut image, PHDR + 16, 0x40_0000);
+    set_u64(&mut image, PHDR + 32, 2 * SECTOR_SIZE as u64);
+    set_u64(&mut image, PHDR + 40, 0x2000);
+
+    image
+}
+
+fn set_u16(image: &mut [u8], offset: usize, value: u16) {
+    le::write_u16(image, offset, value).unwrap();
+}
+
+fn set_u32(image: &mut [u8], offset: usize, value: u32) {
+    le::write_u32(image, offset, value).unwrap();
+}
+
+fn set_u64(image: &mut [u8], offset: usize, value: u64) {
+    le::write_u64(image, offset, value).unwrap();
+}
+
+pub fn test() -> Result<(), String> {
+    let result = load_elf(&RamDisk::from_bytes(&image()));
+    if result != Ok(Entry(VirtualAddress::new(ENTRY))) {
+        return Err(format!("loading the valid executable returned {result:?}"));
+    }
+
+    for case in CASES {
+        let mut image = image();
+        (case.corrupt)(&mut image);
+
+        let result = load_elf(&RamDisk::from_bytes(&image));
+        if result != Err(case.expected) {
+            return Err(format!("{}: expected {:?}, got {result:?}", case.name, case.expected));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elf() {
+        assert_eq!(test(), Ok(()));
+    }
+}
@@ -0,0 +1,109 @@
+//! Checks that all free pages of the kernel pool can be allocated and hold data.
+//!
+//! The pages are taken from the [`PageAllocator`] one by one and chained through their
+//! first word, so the test itself needs no memory. The other words are filled with a
+//! pattern derived from the page address, then the chain is walked to check the
+//! patterns and free the pages again. Getting fewer pages than the pool has free counts
+//! as a failure as well, and so does, in debug builds, not leaving the same pages in use
+//! as before.
+
+use crate::mem::PageAllocator;
+use crate::mem::VirtualAddress;
+use crate::mem::PAGE_SIZE;
+use crate::println;
+use core::ptr::NonNull;
+use enumflags2::BitFlags;
+
+/// Marks pages that are part of the chain, so pages handed out twice are detected.
+const TAG: u64 = 0x6d65_6d74_6573_7421;
+const WORDS: usize = (PAGE_SIZE as usize) / 8;
+
+pub fn run() {
+    let mut head: Option<NonNull<()>> = None;
+    let mut pages = 0;
+    let mut failures = 0;
+    let free = PageAllocator::free_kernel_pages();
+    #[cfg(debug_assertions)]
+    let usage = PageAllocator::usage_snapshot();
+
+    while let Some(page) = PageAllocator::get_pages(BitFlags::empty(), 1) {
+        let words = page.cast::<u64>().as_ptr();
+        let addr = page.as_ptr() as u64;
+
+        // SAFETY: the page was just allocated for us and is only accessed through
+        // the chain until it is freed again below.
+        unsafe {
+            // A page freed by an earlier run may still hold the tag, so only the chain
+            // itself can tell whether the page is already part of it.
+            if words.add(1).read_volatile() == addr ^ TAG && in_chain(head, page) {
+                println!("memtest: page at {:#x} was handed out twice", physical(addr));
+                failures += 1;
+                break;
+            }
+
+            words.write_volatile(head.map_or(0, |p| p.as_ptr() as u64));
+            words.add(1).write_volatile(addr ^ TAG);
+            for i in 2..WORDS {
+                words.add(i).write_volatile(addr);
+            }
+        }
+
+        head = Some(page);
+        pages += 1;
+    }
+
+    if pages != free {
+        println!("memtest: only got {pages} of the {free} free pages");
+        failures += 1;
+    }
+
+    while let Some(page) = head {
+        let words = page.cast::<u64>().as_ptr();
+        let addr = page.as_ptr() as u64;
+
+        // SAFETY: see above, the page is still allocated.
+        unsafe {
+            head = NonNull::new(words.read_volatile() as *mut ());
+
+            let bad_word = (1..WORDS).find(|&i| {
+                let expected = if i == 1 { addr ^ TAG } else { addr };
+                words.add(i).read_volatile() != expected
+            });
+            if let Some(i) = bad_word {
+                println!("memtest: mismatch at {:#x}", physical(addr) + (i as u64) * 8);
+                failures += 1;
+            }
+            words.add(1).write_volatile(0);
+        }
+
+        PageAllocator::free_pages(page, 1);
+    }
+
+    #[cfg(debug_assertions)]
+    if PageAllocator::usage_snapshot() != usage {
+        println!("memtest: the pages in use changed from {usage:?} to {:?}", PageAllocator::usage_snapshot());
+        failures += 1;
+    }
+
+    println!("memtest: {pages} pages tested, {failures} failures");
+}
+
+/// Returns whether `page` is part of the chain starting at `head`.
+///
+/// # Safety
+/// All pages of the chain must still be allocated.
+unsafe fn in_chain(mut head: Option<NonNull<()>>, page: NonNull<()>) -> bool {
+    while let Some(p) = head {
+        if p == page {
+            return true;
+        }
+        head = NonNull::new(p.cast::<u64>().as_ptr().read_volatile() as *mut ());
+    }
+
+    false
+}
+
+/// Returns the physical address of the kernel pool page at `addr`.
+fn physical(addr: u64) -> u64 {
+    VirtualAddress::new(addr).to_kernel_physical().raw()
+}
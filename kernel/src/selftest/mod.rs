@@ -0,0 +1,70 @@
+//! In-kernel tests that are run as command line actions.
+//!
+//! Every test in [`TESTS`] can be run by name with `-test <name>`, which prints
+//! `PASS` or `FAIL: <reason>` for it before shutting down.
+
+use crate::println;
+use ::alloc::string::String;
+
+pub mod alloc;
+pub mod bench;
+mod block;
+mod elf;
+pub mod memtest;
+mod sorted_list;
+mod sync;
+
+/// A test that can be run by name, returning why it failed on failure.
+struct SelfTest {
+    name: &'static str,
+    func: fn() -> Result<(), String>,
+}
+
+const TESTS: &[SelfTest] = &[
+    SelfTest {
+        name: "alloc",
+        func: alloc::test,
+    },
+    SelfTest {
+        name: "block",
+        func: block::test,
+    },
+    SelfTest {
+        name: "elf",
+        func: elf::test,
+    },
+    SelfTest {
+        name: "priority-arith",
+        func: sync::test_priority_arith,
+    },
+    SelfTest {
+        name: "sema-count",
+        func: sync::test_sema_count,
+    },
+    SelfTest {
+        name: "sorted-list",
+        func: sorted_list::test,
+    },
+];
+
+/// Runs the test `name`, prints whether it passed and returns that.
+/// Returns `None` if there is no test with that name.
+pub fn run(name: &str) -> Option<bool> {
+    let test = TESTS.iter().find(|test| test.name == name)?;
+
+    match (test.func)() {
+        Ok(()) => {
+            println!("test-{name}: PASS");
+            Some(true)
+        }
+        Err(reason) => {
+            println!("test-{name}: FAIL: {reason}");
+            Some(false)
+        }
+    }
+}
+
+/// Returns the names of all tests that can be passed to [`run`].
+pub fn names() -> impl Iterator<Item = &'static str> {
+    TESTS.iter().map(|test| test.name)
+}
@@ -0,0 +1,91 @@
+//! Checks that a [`SortedList`] keeps its order and treats equal elements first come,
+//! first served.
+
+use crate::utils::SortedList;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// An element that is ordered only by its key, so equal elements can be told apart.
+#[derive(Debug)]
+struct Item {
+    key: u32,
+    seq: u32,
+}
+
+impl PartialEq for Item {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Item {}
+
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Item {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+pub fn test() -> Result<(), String> {
+    let mut list = SortedList::new();
+    for (seq, key) in [3, 1, 2, 3, 1, 2].into_iter().enumerate() {
+        list.insert(Item { key, seq: seq as u32 });
+    }
+
+    let order: Vec<_> = list.iter().map(|item| (item.key, item.seq)).collect();
+    if order != [(1, 1), (1, 4), (2, 2), (2, 5), (3, 0), (3, 3)] {
+        return Err(format!("wrong order after inserting: {order:?}"));
+    }
+
+    let removed = list.remove(&Item { key: 2, seq: 0 }).map(|item| item.seq);
+    if removed != Some(2) {
+        return Err(format!("removing key 2 removed seq {removed:?} instead of 2"));
+    }
+    let peeked = (list.peek_min().map(|item| item.seq), list.peek_max().map(|item| item.seq));
+    if peeked != (Some(1), Some(0)) {
+        return Err(format!("peek_min and peek_max returned seqs {peeked:?} instead of 1 and 0"));
+    }
+    let max = list.pop_max().map(|item| item.seq);
+    if max != Some(0) {
+        return Err(format!("pop_max returned seq {max:?} instead of 0"));
+    }
+    let min = list.pop_min().map(|item| item.seq);
+    if min != Some(1) {
+        return Err(format!("pop_min returned seq {min:?} instead of 1"));
+    }
+    if list.remove(&Item { key: 7, seq: 0 }).is_some() {
+        return Err(String::from("removed a key that was never inserted"));
+    }
+    if list.len() != 3 {
+        return Err(format!("{} elements left instead of 3", list.len()));
+    }
+
+    while let Some(peeked) = list.peek_max().map(|item| item.seq) {
+        let popped = list.pop_max().map(|item| item.seq);
+        if popped != Some(peeked) {
+            return Err(format!("peek_max returned seq {peeked} but pop_max seq {popped:?}"));
+        }
+    }
+    if list.peek_min().is_some() || list.pop_min().is_some() {
+        return Err(String::from("the drained list still has elements"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_list() {
+        assert_eq!(test(), Ok(()));
+    }
+}
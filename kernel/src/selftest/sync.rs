@@ -0,0 +1,71 @@
+//! Tests for the thread priorities and synchronization primitives that don't need a
+//! scheduler to run.
+
+use crate::threads::sync::Semaphore;
+use crate::threads::ThreadPriority;
+use alloc::format;
+use alloc::string::String;
+
+/// Checks that priority arithmetic saturates at the bounds of the valid range.
+pub fn test_priority_arith() -> Result<(), String> {
+    let cases = [
+        (ThreadPriority::MAX + 1, ThreadPriority::MAX),
+        (ThreadPriority::MAX + u32::MAX, ThreadPriority::MAX),
+        (ThreadPriority::MIN - 1, ThreadPriority::MIN),
+        (ThreadPriority::DEFAULT - ThreadPriority::MAX, ThreadPriority::MIN),
+        (ThreadPriority::DEFAULT + ThreadPriority::MIN, ThreadPriority::DEFAULT),
+    ];
+
+    for (i, (actual, expected)) in cases.into_iter().enumerate() {
+        if actual != expected {
+            return Err(format!("case {i}: got {actual:?}, expected {expected:?}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a semaphore counts `up`s and `down`s and never drops below zero.
+pub fn test_sema_count() -> Result<(), String> {
+    const COUNT: u32 = 3;
+
+    let sema = Semaphore::new(0);
+    if sema.try_down() {
+        return Err(String::from("try_down succeeded on a zero semaphore"));
+    }
+
+    for _ in 0..COUNT {
+        sema.up();
+    }
+    if sema.value() != COUNT {
+        return Err(format!("value is {} after {COUNT} ups", sema.value()));
+    }
+
+    // `down` doesn't wait while the value is positive, so this is fine without a scheduler.
+    sema.down();
+    for i in 1..COUNT {
+        if !sema.try_down() {
+            return Err(format!("try_down {i} failed with value {}", sema.value()));
+        }
+    }
+    if sema.try_down() || sema.value() != 0 {
+        return Err(format!("value is {} after taking everything", sema.value()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_arith() {
+        assert_eq!(test_priority_arith(), Ok(()));
+    }
+
+    #[test]
+    fn sema_count() {
+        assert_eq!(test_sema_count(), Ok(()));
+    }
+}
@@ -0,0 +1,38 @@
+//! Hooks that subsystems register to run before the machine is powered off, e.g. to
+//! flush caches to disk.
+
+use crate::threads::Interrupts;
+use arrayvec::ArrayVec;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+
+pub type ShutdownHook = fn();
+
+/// The maximum number of hooks that can be registered.
+const MAX_HOOKS: usize = 16;
+
+static HOOKS: Spinlock<ArrayVec<ShutdownHook, MAX_HOOKS>> = const_spinlock(ArrayVec::new_const());
+
+/// Registers `hook` to be run on shutdown. Hooks run in the reverse order of their
+/// registration, so subsystems are shut down before the ones they depend on.
+///
+/// # Panics
+/// Panics if more than `MAX_HOOKS` hooks are registered.
+pub fn register_shutdown_hook(hook: ShutdownHook) {
+    let _guard = Interrupts::disable_guarded();
+    if HOOKS.lock().try_push(hook).is_err() {
+        panic!("too many shutdown hooks");
+    }
+}
+
+/// Runs all registered shutdown hooks. Each hook runs at most once, even if this
+/// is called again, e.g. because a hook panicked.
+pub fn run_shutdown_hooks() {
+    let guard = Interrupts::disable_guarded();
+    let hooks = core::mem::take(&mut *HOOKS.lock());
+    drop(guard);
+
+    for hook in hooks.iter().rev() {
+        hook();
+    }
+}
@@ -0,0 +1,127 @@
+//! A copy of the thread list with a fixed layout, so that debuggers can find the kernel
+//! threads without understanding [`Thread`](super::Thread) or the slotmap it lives in.
+//!
+//! The table is exported as the unmangled symbol `PINTOS_THREAD_TABLE` and consists of
+//! plain `repr(C)` integers and bytes only:
+//!
+//! | Offset | Type                      | Field                                     |
+//! |--------|---------------------------|-------------------------------------------|
+//! | 0      | `u32`                     | magic, always `0x50544854` ("THTP")       |
+//! | 4      | `u32`                     | layout version, currently 1               |
+//! | 8      | `u32`                     | number of entries                         |
+//! | 12     | `u32`                     | size of one entry in bytes                |
+//! | 16     | `[DebugThread; ENTRIES]`  | the entries                               |
+//!
+//! Each entry is laid out as follows, unused entries have an id of 0:
+//!
+//! | Offset | Type         | Field                                                  |
+//! |--------|--------------|--------------------------------------------------------|
+//! | 0      | `u32`        | thread id                                              |
+//! | 4      | `u32`        | status: 0 running, 1 ready, 2 blocked, 3 dying         |
+//! | 8      | `u64`        | top of the kernel stack                                |
+//! | 16     | `u32`        | length of the name in bytes                            |
+//! | 20     | `[u8; 44]`   | the name as UTF-8, truncated if longer                 |
+//!
+//! `scripts/gdb_threads.py` walks the table and adds an `info pintos-threads` command.
+
+use super::ThreadStatus;
+use crate::threads::Interrupts;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+
+/// The maximum number of threads that are listed.
+const ENTRIES: usize = 64;
+/// The maximum number of name bytes that are copied into an entry.
+const NAME_LEN: usize = 44;
+
+#[repr(C)]
+struct DebugThreadTable {
+    magic: u32,
+    version: u32,
+    entry_count: u32,
+    entry_size: u32,
+    entries: [DebugThread; ENTRIES],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DebugThread {
+    id: u32,
+    status: u32,
+    stack: u64,
+    name_len: u32,
+    name: [u8; NAME_LEN],
+}
+
+// The layout is documented above and relied on by debugger scripts.
+const _: () = assert!(core::mem::size_of::<DebugThread>() == 64);
+
+impl DebugThread {
+    const UNUSED: DebugThread = DebugThread {
+        id: 0,
+        status: 0,
+        stack: 0,
+        name_len: 0,
+        name: [0; NAME_LEN],
+    };
+}
+
+/// Only ever accessed through raw pointers while holding [`TABLE_LOCK`], a debugger
+/// reading it while the kernel is stopped may see a partially updated entry at worst.
+#[no_mangle]
+#[used]
+static mut PINTOS_THREAD_TABLE: DebugThreadTable = DebugThreadTable {
+    magic: 0x50544854,
+    version: 1,
+    entry_count: ENTRIES as u32,
+    entry_size: core::mem::size_of::<DebugThread>() as u32,
+    entries: [DebugThread::UNUSED; ENTRIES],
+};
+static TABLE_LOCK: Spinlock<()> = const_spinlock(());
+
+/// Inserts or updates the entry of the thread `id`. Threads that don't fit into the
+/// table anymore are silently left out.
+pub(super) fn update(id: u32, status: ThreadStatus, name: &str, stack: usize) {
+    let status = match status {
+        ThreadStatus::Running => 0,
+        ThreadStatus::Ready => 1,
+        ThreadStatus::Blocked => 2,
+        ThreadStatus::Dying => 3,
+    };
+    let name_len = name.len().min(NAME_LEN);
+    let mut entry = DebugThread {
+        id,
+        status,
+        stack: stack as u64,
+        name_len: name_len as u32,
+        name: [0; NAME_LEN],
+    };
+    entry.name[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+
+    with_entries(|entries| {
+        let slot = match entries.iter().position(|e| e.id == id) {
+            Some(idx) => Some(idx),
+            None => entries.iter().position(|e| e.id == 0),
+        };
+        if let Some(idx) = slot {
+            entries[idx] = entry;
+        }
+    });
+}
+
+/// Removes the entry of the thread `id`, if it has one.
+pub(super) fn remove(id: u32) {
+    with_entries(|entries| {
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            *entry = DebugThread::UNUSED;
+        }
+    });
+}
+
+fn with_entries(f: impl FnOnce(&mut [DebugThread; ENTRIES])) {
+    let _guard = Interrupts::disable_guarded();
+    let _lock = TABLE_LOCK.lock();
+    // SAFETY: all accesses from the kernel go through this function while holding the
+    // lock, so no other reference to the entries exists.
+    unsafe { f(&mut *core::ptr::addr_of_mut!(PINTOS_THREAD_TABLE.entries)) }
+}
@@ -1,19 +1,90 @@
+#[cfg(feature = "intr-latency")]
+use super::latency;
+#[cfg(feature = "intr-latency")]
+use super::LatencyHistogram;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
+#[cfg(test)]
+use host as interrupts;
 use spinning_top::const_spinlock;
 use spinning_top::Spinlock;
+#[cfg(not(test))]
 use x86_64::instructions::interrupts;
 use x86_64::structures::idt::InterruptDescriptorTable;
 use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::PrivilegeLevel;
 
-pub type InterruptHandler = fn(InterruptStackFrame);
+/// A function handling an interrupt, which receives the vector that fired so that one
+/// function can serve several vectors.
+pub type InterruptHandler = fn(u8, &mut InterruptContext);
 
 static mut INTERRUPT_TABLE: InterruptDescriptorTable = InterruptDescriptorTable::new();
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
-static HANDLERS: Spinlock<[Option<InterruptHandler>; 256]> = const_spinlock([None; 256]);
+static HANDLERS: Spinlock<[Option<Handler>; 256]> = const_spinlock([None; 256]);
+/// Invoked for interrupts without a registered handler.
+static DEFAULT_HANDLER: Spinlock<InterruptHandler> = const_spinlock(ignore_interrupt);
+
+/// The initial default handler. Every interrupt is already logged on entry, so there is
+/// nothing left to do.
+fn ignore_interrupt(_vector: u8, _context: &mut InterruptContext) {}
+
+/// The kind of gate through which an interrupt handler is entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GateType {
+    /// Interrupts are disabled while the handler runs.
+    Interrupt,
+    /// Interrupts stay enabled while the handler runs.
+    Trap,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+    func: InterruptHandler,
+    name: &'static str,
+    gate: GateType,
+    dpl: PrivilegeLevel,
+}
+
+/// The state of the interrupted code that is passed to an [`InterruptHandler`].
+#[derive(Debug)]
+pub struct InterruptContext {
+    frame: InterruptStackFrame,
+    /// The error code pushed by the CPU for some exceptions.
+    pub error_code: Option<u64>,
+}
+
+impl InterruptContext {
+    /// Returns the stack frame pushed by the CPU.
+    ///
+    /// This is a copy of the frame, so the interrupted code can't be changed through it,
+    /// e.g. to return somewhere else.
+    pub fn frame(&self) -> &InterruptStackFrame {
+        &self.frame
+    }
+}
 
 pub struct Interrupts;
 
+/// Restores the previous interrupt level when dropped, see [`Interrupts::disable_guarded`].
+#[must_use = "interrupts are restored as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct IntrGuard {
+    old_level: bool,
+}
+
+impl IntrGuard {
+    /// Returns whether interrupts were enabled before the guard disabled them.
+    pub fn was_enabled(&self) -> bool {
+        self.old_level
+    }
+}
+
+impl Drop for IntrGuard {
+    fn drop(&mut self) {
+        Interrupts::set_level(self.old_level);
+    }
+}
+
 impl Interrupts {
     /// Initializes the interrupt state.
     ///
@@ -38,6 +109,15 @@ impl Interrupts {
             x86_64::set_general_handler!(&mut INTERRUPT_TABLE, handler);
         }
 
+        // Apply the gates of handlers that were registered before the table existed.
+        let guard = Self::disable_guarded();
+        for (index, handler) in HANDLERS.lock().iter().enumerate() {
+            if let Some(handler) = handler {
+                Self::configure_gate(index as u8, handler.gate, handler.dpl);
+            }
+        }
+        drop(guard);
+
         // SAFETY: the first check in this function ensures that this is only run
         // once and only one a single thread. Therefore, no data races can occur.
         unsafe {
@@ -52,6 +132,15 @@ impl Interrupts {
         prev
     }
 
+    /// Disable interrupts until the returned guard is dropped, which restores the
+    /// previous level. Unlike a manual [`Interrupts::set_level`], this also restores the
+    /// level on early returns.
+    pub fn disable_guarded() -> IntrGuard {
+        IntrGuard {
+            old_level: Self::disable(),
+        }
+    }
+
     /// Enable interrupts nd return the previous level.
     pub fn enable() -> bool {
         let prev = interrupts::are_enabled();
@@ -59,6 +148,20 @@ impl Interrupts {
         prev
     }
 
+    /// Panics if interrupts are enabled, as a precondition check for code that relies on
+    /// not being interrupted.
+    #[track_caller]
+    pub fn assert_disabled() {
+        assert!(!interrupts::are_enabled(), "interrupts must be disabled here");
+    }
+
+    /// Panics if interrupts are disabled, as a precondition check for code that waits for
+    /// something an interrupt has to do.
+    #[track_caller]
+    pub fn assert_enabled() {
+        assert!(interrupts::are_enabled(), "interrupts must be enabled here");
+    }
+
     pub fn set_level(enabled: bool) {
         match enabled {
             true => Self::enable(),
@@ -66,23 +169,131 @@ impl Interrupts {
         };
     }
 
+    /// Sets `func` as the handler for all interrupts that don't have a handler registered,
+    /// e.g. to treat unexpected exceptions as fatal. By default they are only logged.
+    pub fn set_default_handler(func: InterruptHandler) {
+        let _guard = Self::disable_guarded();
+        *DEFAULT_HANDLER.lock() = func;
+    }
+
+    /// Registers `func` as the handler for the external interrupt `index`.
+    ///
+    /// The handler runs with interrupts disabled and cannot be invoked from user mode.
     pub fn register_handler(index: u8, func: InterruptHandler, name: &'static str) {
-        let mut handlers = HANDLERS.lock();
-        handlers[index as usize] = Some(func);
+        Self::register_gate_handler(index, GateType::Interrupt, PrivilegeLevel::Ring0, func, name);
+    }
+
+    /// Registers `func` as the handler for the interrupt `index`, which is entered through
+    /// a gate of type `gate` and may be invoked by code running at privilege level `dpl`
+    /// or more privileged (e.g. `Ring3` for the syscall vector).
+    ///
+    /// # Panics
+    /// Panics if a gate other than the default (interrupt gate, ring 0) is requested for
+    /// an exception that pushes an error code or for a reserved vector.
+    pub fn register_gate_handler(
+        index: u8,
+        gate: GateType,
+        dpl: PrivilegeLevel,
+        func: InterruptHandler,
+        name: &'static str,
+    ) {
+        let _guard = Self::disable_guarded();
+        HANDLERS.lock()[index as usize] = Some(Handler { func, name, gate, dpl });
+        if INITIALIZED.load(Ordering::Relaxed) {
+            Self::configure_gate(index, gate, dpl);
+        }
+    }
+
+    /// Prints every vector with a registered handler, its name and its gate to the log.
+    pub fn dump() {
+        let _guard = Self::disable_guarded();
+
+        crate::log_println!("registered interrupt handlers:");
+        for (index, handler) in HANDLERS.lock().iter().enumerate() {
+            if let Some(handler) = handler {
+                crate::log_println!("  {index:#04x}: {} ({:?} gate, {:?})", handler.name, handler.gate, handler.dpl);
+            }
+        }
+    }
+
+    /// Rebuilds the IDT entry of `index` with the given gate type and privilege level.
+    ///
+    /// Must only be called with interrupts disabled after the table was initialized.
+    fn configure_gate(index: u8, gate: GateType, dpl: PrivilegeLevel) {
+        Self::assert_disabled();
+
+        // The general handler already sets up all entries like this.
+        if gate == GateType::Interrupt && dpl == PrivilegeLevel::Ring0 {
+            return;
+        }
+
+        // SAFETY: interrupts are disabled, so the CPU can't read the entry while it is
+        // modified and nobody else modifies the table concurrently. The handler address
+        // stays the one set up by the general handler.
+        unsafe {
+            let entry = &mut INTERRUPT_TABLE[index as usize];
+            entry
+                .set_handler_addr(entry.handler_addr())
+                .disable_interrupts(gate == GateType::Interrupt)
+                .set_privilege_level(dpl);
+        }
+    }
+
+    /// Returns how long it took interrupts of `vector` from entering the kernel until
+    /// their handler was invoked. This includes looking up the handler, but neither
+    /// logging the interrupt nor the CPU saving the interrupted state before that.
+    #[cfg(feature = "intr-latency")]
+    pub fn latency_stats(vector: u8) -> LatencyHistogram {
+        latency::histogram(vector)
     }
 
     /// The main interrupt entry point.
     ///
-    /// Note: interrupts are disabled by default by the CPU upon entering an
+    /// Note: for interrupt gates, interrupts are disabled by the CPU upon entering an
     /// interrupt handler, so it does not need to be done manually.
     fn interrupt_entry(frame: InterruptStackFrame, index: u8, error_code: Option<u64>) {
         crate::println!("Received interrupt: index:{index}, error_code:{error_code:?}, frame={frame:#?}");
+        // Printing the frame to the serial port takes far longer than the rest, so it is
+        // left out of the latency.
+        #[cfg(feature = "intr-latency")]
+        let entered = latency::timestamp();
 
-        // Invoke a registered interrupt handler if present
-        if let Some(handler) = HANDLERS.lock()[index as usize] {
-            handler(frame);
-        } else {
-            // ToDo: fully implement
-        }
+        // Handlers behind a trap gate may be interrupted, so the lock must neither be
+        // taken with interrupts enabled nor be held while the handler runs.
+        let guard = Self::disable_guarded();
+        let func = match HANDLERS.lock()[index as usize] {
+            Some(handler) => handler.func,
+            None => *DEFAULT_HANDLER.lock(),
+        };
+        drop(guard);
+
+        // Invoke the registered interrupt handler or the default one
+        let mut context = InterruptContext { frame, error_code };
+        #[cfg(feature = "intr-latency")]
+        latency::record(index, entered);
+        func(index, &mut context);
+    }
+}
+
+/// Stands in for the CPU's interrupt flag in unit tests, where `cli` and `sti` fault.
+#[cfg(test)]
+mod host {
+    use std::cell::Cell;
+
+    std::thread_local! {
+        // The kernel starts with interrupts disabled as well.
+        static ENABLED: Cell<bool> = Cell::new(false);
+    }
+
+    pub fn are_enabled() -> bool {
+        ENABLED.with(|e| e.get())
+    }
+
+    pub fn enable() {
+        ENABLED.with(|e| e.set(true));
+    }
+
+    pub fn disable() {
+        ENABLED.with(|e| e.set(false));
     }
 }
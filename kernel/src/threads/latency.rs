@@ -0,0 +1,50 @@
+//! Histograms of how long it takes from entering an interrupt until its handler runs,
+//! see [`Interrupts::latency_stats`](super::Interrupts::latency_stats).
+//!
+//! Only built with the `intr-latency` feature, so the entry path isn't slowed down
+//! otherwise.
+
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+/// The number of buckets in a [`LatencyHistogram`].
+pub const LATENCY_BUCKETS: usize = 32;
+
+static HISTOGRAMS: [[AtomicU32; LATENCY_BUCKETS]; 256] =
+    [const { [const { AtomicU32::new(0) }; LATENCY_BUCKETS] }; 256];
+
+/// The entry latencies of one interrupt vector in TSC cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LatencyHistogram {
+    /// `buckets[i]` counts the entries that took at least `2^(i - 1)` but less than
+    /// `2^i` cycles. The last bucket also counts all slower entries.
+    pub buckets: [u32; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Returns the total number of recorded entries.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|&n| n as u64).sum()
+    }
+}
+
+/// Returns the current value of the time stamp counter.
+pub(super) fn timestamp() -> u64 {
+    // SAFETY: reading the TSC has no side effects.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Records an entry of `vector` that started at the [`timestamp`] `start`.
+pub(super) fn record(vector: u8, start: u64) {
+    let cycles = timestamp().saturating_sub(start);
+    let bucket = ((u64::BITS - cycles.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1);
+
+    HISTOGRAMS[vector as usize][bucket].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the histogram recorded so far for `vector`.
+pub(super) fn histogram(vector: u8) -> LatencyHistogram {
+    LatencyHistogram {
+        buckets: core::array::from_fn(|i| HISTOGRAMS[vector as usize][i].load(Ordering::Relaxed)),
+    }
+}
@@ -1,6 +1,15 @@
 pub use interrupt::*;
+#[cfg(feature = "intr-latency")]
+pub use latency::LatencyHistogram;
+#[cfg(feature = "intr-latency")]
+pub use latency::LATENCY_BUCKETS;
 pub use thread::*;
+pub use work::*;
 
+mod gdb;
 mod interrupt;
+#[cfg(feature = "intr-latency")]
+mod latency;
 pub mod sync;
 mod thread;
+mod work;
@@ -1,3 +1,5 @@
 pub use lock::*;
+pub use semaphore::*;
 
 mod lock;
+mod semaphore;
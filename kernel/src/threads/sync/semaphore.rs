@@ -0,0 +1,170 @@
+use crate::threads::Interrupts;
+use crate::threads::Thread;
+use crate::utils::backoff;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+
+/// A counting semaphore.
+///
+/// The state is only accessed with interrupts disabled, so `up` and `try_down` are
+/// safe to call from interrupt handlers.
+///
+/// Threads waiting in `down` get the value in the order of their effective priority,
+/// threads with the same priority in the order in which they started waiting.
+#[derive(Debug)]
+pub struct Semaphore {
+    state: Spinlock<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    value: u32,
+    /// The threads waiting in `down`, in the order in which they started waiting.
+    waiters: Vec<Arc<Thread>>,
+}
+
+impl State {
+    /// Returns the waiter that should get the value next.
+    fn next_waiter(&self) -> Option<&Arc<Thread>> {
+        // `max_by_key` returns the last maximum, but the first one has waited longest.
+        self.waiters.iter().rev().max_by_key(|t| t.effective_priority())
+    }
+}
+
+impl Semaphore {
+    /// Creates a new [`Semaphore`] with the given initial value.
+    pub const fn new(value: u32) -> Self {
+        Self {
+            state: const_spinlock(State {
+                value,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Waits for the value to become positive and then decrements it.
+    ///
+    /// Must not be called from an interrupt handler or with interrupts disabled,
+    /// since nothing could ever increment the value in that case.
+    pub fn down(&self) {
+        // Without threads there is nobody to order, e.g. early during boot.
+        let Some(thread) = Thread::current() else {
+            while !self.try_down() {
+                Interrupts::assert_enabled();
+                backoff();
+            }
+            return;
+        };
+
+        let mut queued = false;
+        loop {
+            let guard = Interrupts::disable_guarded();
+            let mut state = self.state.lock();
+            if !queued {
+                state.waiters.push(Arc::clone(&thread));
+                queued = true;
+            }
+            if state.value > 0 && state.next_waiter().is_some_and(|t| t.id() == thread.id()) {
+                state.value -= 1;
+                state.waiters.retain(|t| t.id() != thread.id());
+                return;
+            }
+            drop(state);
+            drop(guard);
+
+            // ToDo: block the current thread instead of waiting for the value to change
+            Interrupts::assert_enabled();
+            backoff();
+        }
+    }
+
+    /// Decrements the value if it is positive and no thread is waiting for it, without
+    /// waiting. Returns whether the value was decremented.
+    pub fn try_down(&self) -> bool {
+        let _guard = Interrupts::disable_guarded();
+        let mut state = self.state.lock();
+        if state.value == 0 || !state.waiters.is_empty() {
+            return false;
+        }
+        state.value -= 1;
+
+        true
+    }
+
+    /// Increments the value.
+    ///
+    /// If this hands the value to a waiting thread with a higher priority than the
+    /// current one, the current thread yields to it. Interrupt handlers never yield.
+    pub fn up(&self) {
+        let guard = Interrupts::disable_guarded();
+        let mut state = self.state.lock();
+        state.value += 1;
+        // The priorities can't be looked at from interrupt handlers, which might have
+        // interrupted code holding the locks involved.
+        let woken = match guard.was_enabled() {
+            true => state.next_waiter().map(|t| t.effective_priority()),
+            false => None,
+        };
+        drop(state);
+        drop(guard);
+
+        let Some(woken) = woken else {
+            return;
+        };
+        if Thread::current().is_some_and(|t| woken > t.effective_priority()) {
+            Thread::yield_now();
+        }
+    }
+
+    /// Returns the current value.
+    pub fn value(&self) -> u32 {
+        let _guard = Interrupts::disable_guarded();
+        let value = self.state.lock().value;
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threads::ThreadPriority;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn next_name(state: &State) -> Option<String> {
+        state.next_waiter().map(|t| t.name())
+    }
+
+    #[test]
+    fn waiter_order() {
+        let waiter = |name, priority| Arc::new(Thread::new(name, priority));
+        let mut state = State {
+            value: 0,
+            waiters: vec![
+                waiter("low", ThreadPriority::DEFAULT - 1),
+                waiter("first", ThreadPriority::DEFAULT),
+                waiter("second", ThreadPriority::DEFAULT),
+                waiter("donated", ThreadPriority::MIN),
+            ],
+        };
+
+        // The highest priority goes first, the longest waiting one of them on ties.
+        assert_eq!(next_name(&state).as_deref(), Some("first"));
+
+        // Donations count as well.
+        state.waiters[3].donate_priority(ThreadPriority::DEFAULT);
+        assert_eq!(next_name(&state).as_deref(), Some("first"));
+        state.waiters[3].donate_priority(ThreadPriority::DEFAULT + 1);
+        assert_eq!(next_name(&state).as_deref(), Some("donated"));
+
+        for expected in ["donated", "first", "second", "low"] {
+            let next = state.next_waiter().unwrap().id();
+            assert_eq!(next_name(&state).as_deref(), Some(expected));
+            state.waiters.retain(|t| t.id() != next);
+        }
+        assert_eq!(next_name(&state), None);
+    }
+}
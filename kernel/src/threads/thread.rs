@@ -1,5 +1,5 @@
 //! Each thread structure is stored on the heap for safety reasons (see below).
-//! At the very bottom of the kernel stack memory page is an 8-byte key that
+//! At the very bottom of the kernel stack memory is an 8-byte key that
 //! can be used to retrieve a reference to the thread for this stack from the
 //! global thread list.
 //! This also fixes the problem with the original Pintos implementation where the
@@ -14,13 +14,18 @@
 //! threads in a safe manner in a global map and just index into the map by replacing the
 //! thread structure in the stack page by a key.
 
+use super::gdb;
 use crate::mem::VirtualAddress;
 use crate::mem::PAGE_SIZE;
 use crate::proc::Process;
+use crate::threads::sync::Semaphore;
+use crate::threads::Interrupts;
 use crate::utils::read_esp;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicU32;
 use core::sync::atomic::Ordering;
 use lazy_static::lazy_static;
@@ -32,34 +37,73 @@ lazy_static! {
     static ref ALL_THREADS: Spinlock<SlotMap<ThreadKey, Arc<Thread>>> = Spinlock::new(SlotMap::with_key());
 }
 
+/// The number of pages of each kernel stack.
+///
+/// Must be a power of two: stacks are allocated aligned to their size, so that the
+/// bottom of the stack can be found from any address within it.
+// ToDo: allocate stacks of this size (and alignment) in thread_create
+pub const KERNEL_STACK_PAGES: u64 = 1;
+const KERNEL_STACK_SIZE: u64 = KERNEL_STACK_PAGES * PAGE_SIZE;
+
+const _: () = assert!(KERNEL_STACK_PAGES.is_power_of_two());
+
+/// The number of timer ticks a thread may run before it is preempted.
+const TIME_SLICE: u32 = 4;
+
+/// Whether the scheduler is running and other threads can be switched to.
+// ToDo: set this in thread_start
+static SCHEDULER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// The timer ticks during which no thread could be determined, see
+/// [`Thread::scheduler_profile`].
+static UNATTRIBUTED_SAMPLES: AtomicU32 = AtomicU32::new(0);
+
 #[derive(Debug)]
 pub struct Thread {
     id: ThreadId,
-    status: ThreadStatus,
-    name: String,
+    status: Spinlock<ThreadStatus>,
+    name: Spinlock<String>,
     stack: usize,
     priority: ThreadPriority,
+    /// Priorities donated to this thread, e.g. by threads waiting for a lock it holds.
+    donations: Spinlock<Vec<ThreadPriority>>,
     /// A reference to the parent process if this is a user program.
     process: Option<Arc<Process>>,
+    /// Upped once when the thread exits, see [`Thread::join`].
+    exited: Semaphore,
+    /// The number of timer ticks the thread has run since it was last scheduled.
+    slice_ticks: AtomicU32,
+    /// How many times preemption was disabled, see [`Thread::preempt_disable`].
+    preempt_count: AtomicU32,
+    /// Whether the time slice ran out while preemption was disabled.
+    yield_pending: AtomicBool,
+    /// The number of timer ticks that interrupted this thread, see
+    /// [`Thread::scheduler_profile`].
+    samples: AtomicU32,
     magic: u32,
 }
 
 impl Thread {
     const MAGIC: u32 = 0xcd6abf4b;
 
-    fn new(name: impl ToString, priority: ThreadPriority) -> Self {
+    pub(super) fn new(name: impl ToString, priority: ThreadPriority) -> Self {
         assert!(ThreadPriority::MIN <= priority && priority <= ThreadPriority::MAX);
 
-        let esp = read_esp();
-        let page_bottom = VirtualAddress::new(esp as u64).page_round_down();
+        let stack_bottom = Self::stack_bottom();
 
         Self {
             id: ThreadId::new(),
-            status: ThreadStatus::Blocked,
-            name: name.to_string(),
-            stack: (page_bottom.raw() + PAGE_SIZE) as usize,
+            status: Spinlock::new(ThreadStatus::Blocked),
+            name: Spinlock::new(name.to_string()),
+            stack: (stack_bottom.raw() + KERNEL_STACK_SIZE) as usize,
             priority,
+            donations: Spinlock::new(Vec::new()),
             process: None,
+            exited: Semaphore::new(0),
+            slice_ticks: AtomicU32::new(0),
+            preempt_count: AtomicU32::new(0),
+            yield_pending: AtomicBool::new(false),
+            samples: AtomicU32::new(0),
             magic: Self::MAGIC,
         }
     }
@@ -67,33 +111,277 @@ impl Thread {
     /// Transforms the code that is currently running into a thread.
     pub fn init() {
         //let mut thread = Self::new("main", ThreadPriority::DEFAULT);
-        //thread.status = ThreadStatus::Running;
+        //*thread.status.get_mut() = ThreadStatus::Running;
 
-        //let key = ALL_THREADS.lock().insert(Arc::new(thread));
+        //let thread = Arc::new(thread);
+        //thread.sync_debug_info();
+        //let key = ALL_THREADS.lock().insert(thread);
         //Self::set_current(key);
     }
 
     /// Returns the current running thread.
     pub fn current() -> Option<Arc<Thread>> {
-        let esp = read_esp();
-        let page_bottom = VirtualAddress::new(esp as u64).page_round_down();
+        let stack_bottom = Self::stack_bottom();
         // SAFETY: it is assumed that the kernel stack pointer is always valid to
         // read from. If this was not the case, this code would not even run properly.
-        let raw_key = unsafe { *(page_bottom.raw() as *const u64) };
+        let raw_key = unsafe { *(stack_bottom.raw() as *const u64) };
         let key = ThreadKey::from_raw(raw_key);
 
         ALL_THREADS.lock().get(key).map(|v| Arc::clone(v))
     }
 
+    /// Returns the id of the thread.
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Returns the process the thread belongs to, if it is part of a user program.
+    pub fn process(&self) -> Option<&Arc<Process>> {
+        self.process.as_ref()
+    }
+
+    /// Waits until the thread `id` has exited and frees it afterwards.
+    ///
+    /// Exited threads stay in the thread list until they are joined, so their resources
+    /// are not freed while someone may still join them.
+    pub fn join(id: ThreadId) -> Result<(), JoinError> {
+        if Self::current().is_some_and(|t| t.id == id) {
+            return Err(JoinError::Current);
+        }
+
+        let thread = ALL_THREADS.lock().values().find(|t| t.id == id).cloned();
+        let thread = thread.ok_or(JoinError::NotFound)?;
+
+        thread.exited.down();
+        // Let other threads joining the same thread through as well.
+        thread.exited.up();
+
+        ALL_THREADS.lock().retain(|_, t| t.id != id);
+        gdb::remove(id.0);
+
+        Ok(())
+    }
+
+    /// Exits the current thread and wakes up all threads joining it.
+    pub fn exit() -> ! {
+        if let Some(thread) = Self::current() {
+            thread.set_status(ThreadStatus::Dying);
+            thread.exited.up();
+        }
+
+        loop {
+            // ToDo: switch away for good once the scheduler exists
+            Self::yield_now();
+        }
+    }
+
+    /// Like [`Thread::current`], but returns `None` instead of waiting if the thread
+    /// list is locked.
+    fn try_current() -> Option<Arc<Thread>> {
+        let stack_bottom = Self::stack_bottom();
+        // SAFETY: see `Thread::current`.
+        let raw_key = unsafe { *(stack_bottom.raw() as *const u64) };
+        let key = ThreadKey::from_raw(raw_key);
+
+        ALL_THREADS.try_lock()?.get(key).map(|v| Arc::clone(v))
+    }
+
+    /// Returns whether the scheduler is running.
+    pub fn scheduler_started() -> bool {
+        SCHEDULER_STARTED.load(Ordering::Relaxed)
+    }
+
+    /// Gives up the CPU so that other ready threads can run.
+    pub fn yield_now() {
+        // ToDo: switch to the next ready thread once the scheduler exists
+        core::hint::spin_loop();
+    }
+
+    /// Called by the timer interrupt handler on every tick. Preempts the current thread
+    /// once its time slice is used up, unless preemption is disabled.
+    pub fn tick() {
+        Interrupts::assert_disabled();
+
+        // The interrupted code may hold the thread list lock, so don't wait for it.
+        let Some(thread) = Self::try_current() else {
+            UNATTRIBUTED_SAMPLES.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        if !thread.account_tick() {
+            return;
+        }
+
+        // ToDo: yield once the interrupt returns
+    }
+
+    /// Counts a timer tick that interrupted the thread and returns whether it should be
+    /// preempted now, see [`Thread::tick`].
+    fn account_tick(&self) -> bool {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+
+        if self.slice_ticks.fetch_add(1, Ordering::Relaxed) + 1 < TIME_SLICE {
+            return false;
+        }
+        if self.preempt_count.load(Ordering::Relaxed) > 0 {
+            // Preempt as soon as preemption is enabled again.
+            self.yield_pending.store(true, Ordering::Relaxed);
+            return false;
+        }
+
+        self.slice_ticks.store(0, Ordering::Relaxed);
+        true
+    }
+
+    /// Returns how many timer ticks interrupted each thread since boot or the last
+    /// [`Thread::reset_profile`], which approximates how the CPU time was distributed.
+    pub fn scheduler_profile() -> SchedulerProfile {
+        let threads = ALL_THREADS
+            .lock()
+            .values()
+            .map(|t| ThreadSamples {
+                id: t.id,
+                name: t.name(),
+                samples: t.samples.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        SchedulerProfile {
+            threads,
+            unattributed: UNATTRIBUTED_SAMPLES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Resets the sample counts of [`Thread::scheduler_profile`] to zero.
+    pub fn reset_profile() {
+        for thread in ALL_THREADS.lock().values() {
+            thread.samples.store(0, Ordering::Relaxed);
+        }
+        UNATTRIBUTED_SAMPLES.store(0, Ordering::Relaxed);
+    }
+
+    /// Keeps the current thread from being preempted until the matching call to
+    /// [`Thread::preempt_enable`]. Calls can be nested.
+    ///
+    /// Unlike disabling interrupts, interrupts are still handled in the meantime.
+    pub fn preempt_disable() {
+        if let Some(thread) = Self::current() {
+            thread.disable_preemption();
+        }
+    }
+
+    /// Allows the current thread to be preempted again after [`Thread::preempt_disable`]
+    /// and yields if its time slice ran out in the meantime.
+    pub fn preempt_enable() {
+        if Self::current().is_some_and(|t| t.enable_preemption()) {
+            Self::yield_now();
+        }
+    }
+
+    fn disable_preemption(&self) {
+        self.preempt_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Undoes one [`Thread::disable_preemption`] and returns whether the thread should
+    /// yield now, because its time slice ran out in the meantime.
+    fn enable_preemption(&self) -> bool {
+        let old = self.preempt_count.fetch_sub(1, Ordering::Relaxed);
+        assert!(old > 0, "preempt_enable without preempt_disable");
+        if old == 1 && self.yield_pending.swap(false, Ordering::Relaxed) {
+            self.slice_ticks.store(0, Ordering::Relaxed);
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns the current status of the thread.
+    pub fn status(&self) -> ThreadStatus {
+        *self.status.lock()
+    }
+
+    /// Changes the status of the thread. This is the only way the status may change
+    /// after the thread was created.
+    ///
+    /// # Panics
+    /// Panics if the transition is not allowed (see [`ThreadStatus::can_transition_to`]).
+    pub fn set_status(&self, new: ThreadStatus) {
+        let mut status = self.status.lock();
+        let old = *status;
+        assert!(old.can_transition_to(new), "illegal thread status transition from {old:?} to {new:?}");
+        *status = new;
+        drop(status);
+
+        self.sync_debug_info();
+    }
+
+    /// Returns the priority of the thread, ignoring any donations.
+    pub fn base_priority(&self) -> ThreadPriority {
+        self.priority
+    }
+
+    /// Returns the priority the thread should be scheduled with, which is the highest of
+    /// its base priority and all priorities currently donated to it.
+    pub fn effective_priority(&self) -> ThreadPriority {
+        self.donations.lock().iter().copied().fold(self.priority, ThreadPriority::max)
+    }
+
+    /// Donates `priority` to the thread until it is revoked again.
+    pub fn donate_priority(&self, priority: ThreadPriority) {
+        self.donations.lock().push(priority);
+    }
+
+    /// Revokes a donation of `priority` made with [`Thread::donate_priority`].
+    pub fn revoke_priority(&self, priority: ThreadPriority) {
+        let mut donations = self.donations.lock();
+        if let Some(idx) = donations.iter().position(|&p| p == priority) {
+            donations.swap_remove(idx);
+        }
+    }
+
+    /// Returns the name of the thread.
+    pub fn name(&self) -> String {
+        self.name.lock().clone()
+    }
+
+    /// Calls `f` with the name of the thread, without copying it to the heap.
+    pub fn with_name<R>(&self, f: impl FnOnce(&str) -> R) -> R {
+        f(&self.name.lock())
+    }
+
+    /// Renames the current thread. Does nothing if there is no current thread yet.
+    pub fn set_name(name: impl ToString) {
+        if let Some(thread) = Self::current() {
+            *thread.name.lock() = name.to_string();
+            thread.sync_debug_info();
+        }
+    }
+
+    /// Updates the entry of the thread in the debugger thread table (see [`gdb`]).
+    ///
+    /// This runs on every status change, so the name is borrowed instead of copied to the
+    /// heap.
+    fn sync_debug_info(&self) {
+        let status = self.status();
+        self.with_name(|name| gdb::update(self.id.0, status, name, self.stack));
+    }
+
     fn set_current(key: ThreadKey) {
-        let esp = read_esp();
-        let page_bottom = VirtualAddress::new(esp as u64).page_round_down();
+        let stack_bottom = Self::stack_bottom();
         // SAFETY: it is assumed that the kernel stack pointer is always valid to
         // read from. If this was not the case, this code would not even run properly.
         unsafe {
-            *(page_bottom.raw() as *mut u64) = key.to_raw();
+            *(stack_bottom.raw() as *mut u64) = key.to_raw();
         }
     }
+
+    /// Returns the lowest address of the current kernel stack, where its key is stored.
+    ///
+    /// This works from anywhere in the stack because stacks are aligned to their size.
+    fn stack_bottom() -> VirtualAddress {
+        let esp = read_esp() as u64;
+
+        VirtualAddress::new(esp & !(KERNEL_STACK_SIZE - 1))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -104,6 +392,49 @@ pub enum ThreadStatus {
     Dying,
 }
 
+impl ThreadStatus {
+    /// Returns whether a thread may go from `self` to the `new` status.
+    ///
+    /// Only the running thread can give up the CPU (by yielding, blocking or exiting),
+    /// blocked threads must be woken up before they can run again and ready threads
+    /// only start running when the scheduler picks them.
+    pub const fn can_transition_to(self, new: ThreadStatus) -> bool {
+        use ThreadStatus::*;
+
+        matches!((self, new), (Running, Ready | Blocked | Dying) | (Ready, Running) | (Blocked, Ready))
+    }
+}
+
+/// A snapshot of the timer ticks each thread was running for, see
+/// [`Thread::scheduler_profile`].
+#[derive(Debug, Clone)]
+pub struct SchedulerProfile {
+    pub threads: Vec<ThreadSamples>,
+    /// The ticks in which the running thread couldn't be determined, e.g. before threads
+    /// were set up.
+    pub unattributed: u32,
+}
+
+/// The number of timer ticks that interrupted one thread.
+#[derive(Debug, Clone)]
+pub struct ThreadSamples {
+    pub id: ThreadId,
+    pub name: String,
+    pub samples: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JoinError {
+    /// There is no thread with the given id.
+    NotFound,
+    /// A thread can't wait for itself to exit.
+    Current,
+}
+
+/// The scheduling priority of a thread.
+///
+/// Adding to and subtracting from a priority saturates at [`ThreadPriority::MIN`] and
+/// [`ThreadPriority::MAX`] instead of leaving the valid range.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ThreadPriority(u32);
 
@@ -111,13 +442,24 @@ impl ThreadPriority {
     pub const MIN: ThreadPriority = ThreadPriority(0);
     pub const DEFAULT: ThreadPriority = ThreadPriority(31);
     pub const MAX: ThreadPriority = ThreadPriority(63);
+
+    /// Creates a priority from `val`, clamped to the valid range.
+    ///
+    /// [`ThreadPriority::MIN`] is zero, so only the upper bound needs to be checked.
+    const fn clamped(val: u32) -> Self {
+        if val > Self::MAX.0 {
+            Self::MAX
+        } else {
+            Self(val)
+        }
+    }
 }
 
 impl core::ops::Add<ThreadPriority> for ThreadPriority {
     type Output = Self;
 
     fn add(self, rhs: ThreadPriority) -> Self::Output {
-        Self(self.0 + rhs.0)
+        self + rhs.0
     }
 }
 
@@ -125,7 +467,7 @@ impl core::ops::Add<u32> for ThreadPriority {
     type Output = Self;
 
     fn add(self, rhs: u32) -> Self::Output {
-        Self(self.0 + rhs)
+        Self::clamped(self.0.saturating_add(rhs))
     }
 }
 
@@ -133,7 +475,7 @@ impl core::ops::Sub<ThreadPriority> for ThreadPriority {
     type Output = Self;
 
     fn sub(self, rhs: ThreadPriority) -> Self::Output {
-        Self(self.0 - rhs.0)
+        self - rhs.0
     }
 }
 
@@ -141,7 +483,7 @@ impl core::ops::Sub<u32> for ThreadPriority {
     type Output = Self;
 
     fn sub(self, rhs: u32) -> Self::Output {
-        Self(self.0 - rhs)
+        Self::clamped(self.0.saturating_sub(rhs))
     }
 }
 
@@ -171,3 +513,81 @@ impl ThreadKey {
         self.0.as_ffi()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preemption_is_deferred_while_disabled() {
+        let thread = Thread::new("test", ThreadPriority::DEFAULT);
+
+        thread.disable_preemption();
+        thread.disable_preemption();
+        for _ in 0..TIME_SLICE {
+            assert!(!thread.account_tick());
+        }
+        assert!(thread.yield_pending.load(Ordering::Relaxed));
+
+        // Only the outermost enable yields.
+        assert!(!thread.enable_preemption());
+        assert!(thread.yield_pending.load(Ordering::Relaxed));
+        assert!(thread.enable_preemption());
+        assert!(!thread.yield_pending.load(Ordering::Relaxed));
+
+        // The yield started a new time slice.
+        for _ in 1..TIME_SLICE {
+            assert!(!thread.account_tick());
+        }
+        assert!(thread.account_tick());
+        assert_eq!(thread.samples.load(Ordering::Relaxed), 2 * TIME_SLICE);
+    }
+
+    #[test]
+    fn status_transitions() {
+        use ThreadStatus::*;
+
+        let all = [Running, Ready, Blocked, Dying];
+        let allowed = [
+            (Running, Ready),
+            (Running, Blocked),
+            (Running, Dying),
+            (Ready, Running),
+            (Blocked, Ready),
+        ];
+        for from in all {
+            for to in all {
+                let expected = allowed.contains(&(from, to));
+                assert_eq!(from.can_transition_to(to), expected, "{from:?} -> {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn donation_chain() {
+        let low = Thread::new("low", ThreadPriority::DEFAULT - 10);
+        let mid = Thread::new("mid", ThreadPriority::DEFAULT);
+        let high = Thread::new("high", ThreadPriority::DEFAULT + 10);
+
+        // `high` waits for a lock held by `mid`, which waits for a lock held by `low`.
+        mid.donate_priority(high.effective_priority());
+        low.donate_priority(mid.effective_priority());
+        assert_eq!(mid.effective_priority(), high.base_priority());
+        assert_eq!(low.effective_priority(), high.base_priority());
+        assert_eq!(low.base_priority(), ThreadPriority::DEFAULT - 10);
+
+        // The highest of several donations wins, and revoking one leaves the others.
+        let other = ThreadPriority::DEFAULT + 5;
+        low.donate_priority(other);
+        assert_eq!(low.effective_priority(), high.base_priority());
+        low.revoke_priority(high.base_priority());
+        assert_eq!(low.effective_priority(), other);
+        low.revoke_priority(other);
+        assert_eq!(low.effective_priority(), low.base_priority());
+
+        // Donations below the base priority don't lower it.
+        mid.revoke_priority(high.base_priority());
+        mid.donate_priority(low.base_priority());
+        assert_eq!(mid.effective_priority(), mid.base_priority());
+    }
+}
@@ -0,0 +1,74 @@
+//! Deferred work for interrupt handlers.
+//!
+//! Interrupt handlers must be short, so anything that takes longer than acknowledging
+//! the device should be pushed onto the [`WorkQueue`] and is then run later by the
+//! worker with interrupts enabled (similar to bottom halves in other kernels).
+
+use crate::threads::sync::Semaphore;
+use crate::threads::Interrupts;
+use arrayvec::ArrayVec;
+use spinning_top::const_spinlock;
+use spinning_top::Spinlock;
+
+/// A unit of deferred work.
+pub type Work = fn();
+
+const CAPACITY: usize = 32;
+
+static WORK_LIST: Spinlock<ArrayVec<Work, CAPACITY>> = const_spinlock(ArrayVec::new_const());
+/// Counts the number of entries in `WORK_LIST`.
+static PENDING: Semaphore = Semaphore::new(0);
+
+pub struct WorkQueue;
+
+impl WorkQueue {
+    /// The maximum number of work items that can be queued at once.
+    pub const CAPACITY: usize = CAPACITY;
+
+    /// Queues `work` to be run later by the worker. Returns `false` if the queue is full.
+    ///
+    /// The work list is preallocated, so this never allocates and is safe to call from
+    /// interrupt context.
+    pub fn schedule_work(work: Work) -> bool {
+        let guard = Interrupts::disable_guarded();
+        let queued = WORK_LIST.lock().try_push(work).is_ok();
+        drop(guard);
+
+        if queued {
+            PENDING.up();
+        }
+
+        queued
+    }
+
+    /// Runs queued work forever, waiting for new work when the queue is empty.
+    ///
+    /// This is the body of the dedicated worker thread.
+    // ToDo: spawn the worker thread once thread creation exists
+    pub fn run_worker() -> ! {
+        loop {
+            PENDING.down();
+            Self::run_next();
+        }
+    }
+
+    /// Runs all work that is currently queued without waiting for more.
+    pub fn run_pending() {
+        while PENDING.try_down() {
+            Self::run_next();
+        }
+    }
+
+    /// Removes the oldest work item from the queue and runs it.
+    ///
+    /// Must only be called after a successful `down` on `PENDING`.
+    fn run_next() {
+        let guard = Interrupts::disable_guarded();
+        let work = WORK_LIST.lock().pop_at(0);
+        drop(guard);
+
+        if let Some(work) = work {
+            work();
+        }
+    }
+}
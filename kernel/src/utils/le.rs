@@ -0,0 +1,95 @@
+//! Bounds-checked reads and writes of little-endian integers in byte slices, e.g. for
+//! parsing on-disk structures like ELF headers and partition tables.
+//!
+//! All functions return `None` if the value at `offset` doesn't fit into the slice.
+
+/// Returns the `N` bytes starting at `offset`.
+fn bytes_at<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Returns the `N` bytes starting at `offset` for modification.
+fn bytes_at_mut<const N: usize>(bytes: &mut [u8], offset: usize) -> Option<&mut [u8; N]> {
+    bytes.get_mut(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Reads the `u16` at `offset`.
+pub fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes_at(bytes, offset).map(u16::from_le_bytes)
+}
+
+/// Reads the `u32` at `offset`.
+pub fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes_at(bytes, offset).map(u32::from_le_bytes)
+}
+
+/// Reads the `u64` at `offset`.
+pub fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    bytes_at(bytes, offset).map(u64::from_le_bytes)
+}
+
+/// Writes `value` to `offset`.
+pub fn write_u16(bytes: &mut [u8], offset: usize, value: u16) -> Option<()> {
+    *bytes_at_mut(bytes, offset)? = value.to_le_bytes();
+    Some(())
+}
+
+/// Writes `value` to `offset`.
+pub fn write_u32(bytes: &mut [u8], offset: usize, value: u32) -> Option<()> {
+    *bytes_at_mut(bytes, offset)? = value.to_le_bytes();
+    Some(())
+}
+
+/// Writes `value` to `offset`.
+pub fn write_u64(bytes: &mut [u8], offset: usize, value: u64) -> Option<()> {
+    *bytes_at_mut(bytes, offset)? = value.to_le_bytes();
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTES: [u8; 10] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a];
+
+    #[test]
+    fn reads_up_to_the_end() {
+        let len = BYTES.len();
+
+        assert_eq!(read_u16(&BYTES, 0), Some(0x0201));
+        assert_eq!(read_u16(&BYTES, len - 2), Some(0x0a09));
+        assert_eq!(read_u16(&BYTES, len - 1), None);
+        assert_eq!(read_u32(&BYTES, len - 4), Some(0x0a09_0807));
+        assert_eq!(read_u32(&BYTES, len - 3), None);
+        assert_eq!(read_u64(&BYTES, len - 8), Some(0x0a09_0807_0605_0403));
+        assert_eq!(read_u64(&BYTES, len - 7), None);
+        assert_eq!(read_u64(&BYTES[..7], 0), None);
+    }
+
+    #[test]
+    fn offsets_that_overflow() {
+        assert_eq!(read_u16(&BYTES, usize::MAX), None);
+        assert_eq!(read_u32(&BYTES, usize::MAX - 2), None);
+        assert_eq!(read_u64(&BYTES, usize::MAX), None);
+
+        let mut bytes = BYTES;
+        assert_eq!(write_u64(&mut bytes, usize::MAX - 6, 0), None);
+        assert_eq!(bytes, BYTES);
+    }
+
+    #[test]
+    fn writes_up_to_the_end() {
+        let mut bytes = [0; 8];
+        let len = bytes.len();
+
+        assert_eq!(write_u16(&mut bytes, len - 2, 0xbbaa), Some(()));
+        assert_eq!(write_u16(&mut bytes, len - 1, 0), None);
+        assert_eq!(write_u32(&mut bytes, len - 3, 0), None);
+        assert_eq!(write_u32(&mut bytes, 0, 0x4433_2211), Some(()));
+        assert_eq!(bytes, [0x11, 0x22, 0x33, 0x44, 0, 0, 0xaa, 0xbb]);
+
+        assert_eq!(write_u64(&mut bytes, 1, 0), None);
+        assert_eq!(write_u64(&mut bytes, 0, 0x0807_0605_0403_0201), Some(()));
+        assert_eq!(read_u64(&bytes, 0), Some(0x0807_0605_0403_0201));
+    }
+}
@@ -0,0 +1,237 @@
+use arrayvec::ArrayString;
+use arrayvec::ArrayVec;
+
+/// The maximum length of an edited line in bytes.
+pub const LINE_LEN: usize = 128;
+/// The number of previous lines that can be recalled.
+const HISTORY_LEN: usize = 16;
+
+/// Where the editor is within an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum EscapeState {
+    None,
+    /// An ESC was received.
+    Escape,
+    /// `ESC [` was received and the final byte is expected.
+    Csi,
+}
+
+/// Turns the bytes typed on a terminal into lines, with support for backspace, clearing
+/// the line with Ctrl-U and recalling previous lines with the up and down arrow keys.
+///
+/// The editor does no I/O itself. Every byte is passed to [`LineEditor::feed`], which
+/// hands the text that the terminal should show to `echo`.
+#[derive(Debug, Clone)]
+pub struct LineEditor {
+    prompt: &'static str,
+    line: ArrayString<LINE_LEN>,
+    /// The previous lines, the most recent one last.
+    history: ArrayVec<ArrayString<LINE_LEN>, HISTORY_LEN>,
+    /// How many lines back in the history the current line was recalled from.
+    history_pos: Option<usize>,
+    escape: EscapeState,
+    /// Whether the previous byte was a CR, so that the LF of a CR LF pair is skipped.
+    after_cr: bool,
+}
+
+impl LineEditor {
+    /// Creates a new [`LineEditor`] that shows `prompt` in front of every line.
+    pub fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: ArrayString::new(),
+            history: ArrayVec::new(),
+            history_pos: None,
+            escape: EscapeState::None,
+            after_cr: false,
+        }
+    }
+
+    /// Returns the prompt, which has to be shown before the first line is typed and is
+    /// shown again automatically whenever the line is redrawn.
+    pub fn prompt(&self) -> &'static str {
+        self.prompt
+    }
+
+    /// Processes the received `byte` and returns the line once it is completed.
+    ///
+    /// Printable characters that don't fit into the line anymore are dropped.
+    pub fn feed(&mut self, byte: u8, mut echo: impl FnMut(&str)) -> Option<ArrayString<LINE_LEN>> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+
+        match (self.escape, byte) {
+            (EscapeState::None, 0x1b) => self.escape = EscapeState::Escape,
+            (EscapeState::Escape, b'[') => self.escape = EscapeState::Csi,
+            (EscapeState::Csi, b'A') => {
+                self.escape = EscapeState::None;
+                self.recall_older(&mut echo);
+            }
+            (EscapeState::Csi, b'B') => {
+                self.escape = EscapeState::None;
+                self.recall_newer(&mut echo);
+            }
+            // Ignore any escape sequence that isn't supported.
+            (EscapeState::Escape | EscapeState::Csi, _) => self.escape = EscapeState::None,
+            (EscapeState::None, b'\n') if after_cr => {}
+            (EscapeState::None, b'\r' | b'\n') => {
+                echo("\r\n");
+                return Some(self.finish_line());
+            }
+            (EscapeState::None, 0x08 | 0x7f) => {
+                if self.line.pop().is_some() {
+                    echo("\x08 \x08");
+                }
+            }
+            // Ctrl-U
+            (EscapeState::None, 0x15) => {
+                self.line.clear();
+                self.redraw(&mut echo);
+            }
+            (EscapeState::None, 0x20..=0x7e) => {
+                let c = byte as char;
+                if self.line.try_push(c).is_ok() {
+                    echo(c.encode_utf8(&mut [0; 1]));
+                }
+            }
+            (EscapeState::None, _) => {}
+        }
+
+        None
+    }
+
+    fn finish_line(&mut self) -> ArrayString<LINE_LEN> {
+        let line = core::mem::take(&mut self.line);
+        self.history_pos = None;
+
+        if !line.is_empty() {
+            if self.history.is_full() {
+                self.history.remove(0);
+            }
+            self.history.push(line);
+        }
+
+        line
+    }
+
+    fn recall_older(&mut self, echo: &mut impl FnMut(&str)) {
+        let pos = match self.history_pos {
+            Some(pos) => pos + 1,
+            None => 0,
+        };
+        if pos < self.history.len() {
+            self.history_pos = Some(pos);
+            self.line = self.history[self.history.len() - 1 - pos];
+            self.redraw(echo);
+        }
+    }
+
+    fn recall_newer(&mut self, echo: &mut impl FnMut(&str)) {
+        match self.history_pos {
+            None => return,
+            Some(0) => {
+                self.history_pos = None;
+                self.line.clear();
+            }
+            Some(pos) => {
+                self.history_pos = Some(pos - 1);
+                self.line = self.history[self.history.len() - pos];
+            }
+        }
+        self.redraw(echo);
+    }
+
+    /// Replaces the shown line with the current one.
+    fn redraw(&self, echo: &mut impl FnMut(&str)) {
+        echo("\r");
+        echo(self.prompt);
+        echo(&self.line);
+        // Clear what is left of a longer previous line.
+        echo("\x1b[K");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    /// Feeds `bytes` to `editor` and returns the completed lines and everything echoed.
+    fn feed_all(editor: &mut LineEditor, bytes: &[u8]) -> (Vec<String>, String) {
+        let mut echoed = String::new();
+        let lines = bytes
+            .iter()
+            .filter_map(|&b| editor.feed(b, |s| echoed.push_str(s)))
+            .map(|line| String::from(line.as_str()))
+            .collect();
+
+        (lines, echoed)
+    }
+
+    #[test]
+    fn backspace() {
+        let mut editor = LineEditor::new("> ");
+
+        let (lines, echoed) = feed_all(&mut editor, b"\x08ab\x08c\x7f\x7fd\r");
+        assert_eq!(lines, ["d"]);
+        // Nothing is erased from an empty line.
+        assert_eq!(echoed, "ab\x08 \x08c\x08 \x08\x08 \x08d\r\n");
+    }
+
+    #[test]
+    fn ctrl_u() {
+        let mut editor = LineEditor::new("> ");
+
+        let (lines, echoed) = feed_all(&mut editor, b"abc\x15x\r");
+        assert_eq!(lines, ["x"]);
+        assert_eq!(echoed, "abc\r> \x1b[Kx\r\n");
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut editor = LineEditor::new("> ");
+
+        // CR, LF and CR LF each end one line, LF CR ends two.
+        let (lines, _) = feed_all(&mut editor, b"a\rb\nc\r\nd\n\re\r\r");
+        assert_eq!(lines, ["a", "b", "c", "d", "", "e", ""]);
+    }
+
+    #[test]
+    fn bounded_line() {
+        let mut editor = LineEditor::new("> ");
+        let mut bytes = [b'x'; LINE_LEN + 8];
+        bytes[LINE_LEN + 7] = b'\r';
+
+        let (lines, _) = feed_all(&mut editor, &bytes);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), LINE_LEN);
+    }
+
+    #[test]
+    fn history() {
+        const UP: &[u8] = b"\x1b[A";
+        const DOWN: &[u8] = b"\x1b[B";
+        let mut editor = LineEditor::new("> ");
+        feed_all(&mut editor, b"one\r\rtwo\r");
+
+        // Empty lines are not remembered.
+        let (lines, _) = feed_all(&mut editor, &[UP, UP, UP, b"\r"].concat());
+        assert_eq!(lines, ["one"]);
+
+        // Recalled lines are remembered again when they are entered.
+        let (lines, echoed) = feed_all(&mut editor, &[UP, UP, DOWN, b"!\r"].concat());
+        assert_eq!(lines, ["one!"]);
+        assert_eq!(echoed, "\r> one\x1b[K\r> two\x1b[K\r> one\x1b[K!\r\n");
+
+        // Going down past the most recent line leaves an empty line.
+        let (lines, _) = feed_all(&mut editor, &[UP, DOWN, DOWN, b"x\r"].concat());
+        assert_eq!(lines, ["x"]);
+
+        // Only the last HISTORY_LEN lines are kept.
+        for i in 0..HISTORY_LEN {
+            feed_all(&mut editor, alloc::format!("{i}\r").as_bytes());
+        }
+        let (lines, _) = feed_all(&mut editor, &[UP.repeat(HISTORY_LEN + 4), b"\r".to_vec()].concat());
+        assert_eq!(lines, ["0"]);
+    }
+}
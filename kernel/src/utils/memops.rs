@@ -0,0 +1,50 @@
+//! Bulk memory operations using the x86 string instructions.
+//!
+//! These are meant for large, mostly aligned regions such as whole pages. The bulk of
+//! the region is handled 8 bytes at a time with `rep stosq`/`rep movsq`, the remaining
+//! bytes byte-wise.
+
+/// Sets `len` bytes starting at `ptr` to zero.
+///
+/// # Safety
+/// Same as for [`core::ptr::write_bytes`]: `ptr` must be valid for writes of `len` bytes.
+pub unsafe fn fast_zero(ptr: *mut u8, len: usize) {
+    let qwords = len / 8;
+    let tail = len % 8;
+
+    // SAFETY: the caller guarantees that the whole range is writable. The direction
+    // flag is cleared by the ABI, so `rep stosq` moves forwards.
+    unsafe {
+        core::arch::asm!(
+            "rep stosq",
+            inout("rcx") qwords => _,
+            inout("rdi") ptr => _,
+            in("rax") 0u64,
+            options(nostack, preserves_flags),
+        );
+        core::ptr::write_bytes(ptr.add(qwords * 8), 0, tail);
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst`.
+///
+/// # Safety
+/// Same as for [`core::ptr::copy_nonoverlapping`]: both ranges must be valid for `len`
+/// bytes and must not overlap.
+pub unsafe fn fast_copy(dst: *mut u8, src: *const u8, len: usize) {
+    let qwords = len / 8;
+    let tail = len % 8;
+
+    // SAFETY: the caller guarantees that both ranges are valid and don't overlap. The
+    // direction flag is cleared by the ABI, so `rep movsq` moves forwards.
+    unsafe {
+        core::arch::asm!(
+            "rep movsq",
+            inout("rcx") qwords => _,
+            inout("rdi") dst => _,
+            inout("rsi") src => _,
+            options(nostack, preserves_flags),
+        );
+        core::ptr::copy_nonoverlapping(src.add(qwords * 8), dst.add(qwords * 8), tail);
+    }
+}
@@ -1,7 +1,19 @@
+pub use line_editor::*;
+pub use memops::*;
+pub use random::*;
+pub use sorted_list::*;
+
+use crate::threads::Thread;
 use bitvec::prelude::BitOrder;
 use bitvec::slice::BitSlice;
 use bitvec::store::BitStore;
 
+pub mod le;
+mod line_editor;
+mod memops;
+mod random;
+mod sorted_list;
+
 pub trait BitSliceScan {
     fn scan(&self, start: usize, num: usize, val: bool) -> Option<usize>;
 
@@ -17,7 +29,7 @@ impl<T: BitStore, O: BitOrder> BitSliceScan for BitSlice<T, O> {
         if (start + num) <= self.len() {
             let last = self.len() - num;
             for i in start..=last {
-                let sub_slice = self.get(start..(start + num))?;
+                let sub_slice = self.get(i..(i + num))?;
                 let is_valid = match val {
                     true => sub_slice.all(),
                     false => sub_slice.not_any(),
@@ -33,20 +45,64 @@ impl<T: BitStore, O: BitOrder> BitSliceScan for BitSlice<T, O> {
 
     fn scan_and_flip(&mut self, start: usize, num: usize, val: bool) -> Option<usize> {
         let idx = self.scan(start, num, val)?;
-        self.get_mut(start..(start + num))?.fill(val);
+        self.get_mut(idx..(idx + num))?.fill(!val);
 
         Some(idx)
     }
 }
 
+/// Waits a little while before the caller checks a condition again.
+///
+/// Spins before the scheduler is running and yields to other threads afterwards, so
+/// the same waiting loop can be used both during early boot and later on.
+pub fn backoff() {
+    if Thread::scheduler_started() {
+        Thread::yield_now();
+    } else {
+        core::hint::spin_loop();
+    }
+}
+
 /// Reads and returns the value of the stack pointer register.
 pub fn read_esp() -> usize {
     let esp: usize;
 
     // SAFETY: it is safe to read from a register.
     unsafe {
-        core::arch::asm!("mov {}, [esp]", out(reg) esp, options(nostack, nomem, preserves_flags));
+        core::arch::asm!("mov {}, rsp", out(reg) esp, options(nostack, nomem, preserves_flags));
     }
 
     esp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_checks_every_window() {
+        let mut words = [0usize];
+        let bits = &mut BitSlice::<usize>::from_slice_mut(&mut words)[..9];
+        for i in [0, 2, 5] {
+            bits.set(i, true);
+        }
+
+        assert_eq!(bits.scan(0, 2, false), Some(3));
+        assert_eq!(bits.scan(0, 3, false), Some(6));
+        assert_eq!(bits.scan(0, 4, false), None);
+        assert_eq!(bits.scan(3, 1, true), Some(5));
+        assert_eq!(bits.scan(10, 1, false), None);
+    }
+
+    #[test]
+    fn scan_and_flip_flips_the_found_run() {
+        let mut words = [0usize];
+        let bits = &mut BitSlice::<usize>::from_slice_mut(&mut words)[..6];
+        bits.set(0, true);
+
+        assert_eq!(bits.scan_and_flip(0, 2, false), Some(1));
+        assert_eq!(bits.scan_and_flip(0, 2, false), Some(3));
+        assert_eq!(bits.scan_and_flip(0, 2, false), None);
+        assert!(bits[..5].all() && !bits[5]);
+    }
+}
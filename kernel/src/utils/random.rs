@@ -0,0 +1,46 @@
+/// A small xorshift64* pseudo-random number generator.
+///
+/// This is NOT cryptographically secure and is only intended for tests and
+/// randomized algorithms inside the kernel.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Creates a new [`Random`] generator from the given seed.
+    pub const fn new(seed: u64) -> Self {
+        // A state of zero would only ever produce zeros.
+        let state = if seed == 0 { 0x9e3779b97f4a7c15 } else { seed };
+        Self { state }
+    }
+
+    /// Returns the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+
+        x.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a random number in `0..bound`.
+    ///
+    /// # Panics
+    /// Panics if `bound` is zero.
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        assert!(bound > 0);
+
+        self.next_u64() % bound
+    }
+
+    /// Shuffles the slice in place.
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.next_below((i + 1) as u64) as usize;
+            slice.swap(i, j);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::vec::Vec;
+
+/// A list that keeps its elements sorted in ascending order.
+///
+/// Equal elements keep the order in which they were inserted, so e.g. threads with the
+/// same priority are handled first come, first served. Intended for the ready list and
+/// the sleep list, which hold few elements, so inserting and removing is `O(n)`.
+#[derive(Debug, Clone)]
+pub struct SortedList<T: Ord> {
+    items: Vec<T>,
+}
+
+impl<T: Ord> SortedList<T> {
+    /// Creates a new, empty [`SortedList`].
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    /// Inserts `item` after all elements that are less than or equal to it.
+    pub fn insert(&mut self, item: T) {
+        let idx = self.items.partition_point(|x| x <= &item);
+        self.items.insert(idx, item);
+    }
+
+    /// Removes and returns the smallest element, the one inserted first if there are
+    /// several.
+    pub fn pop_min(&mut self) -> Option<T> {
+        match self.items.is_empty() {
+            true => None,
+            false => Some(self.items.remove(0)),
+        }
+    }
+
+    /// Removes and returns the largest element, the one inserted first if there are
+    /// several.
+    pub fn pop_max(&mut self) -> Option<T> {
+        let idx = self.max_idx()?;
+
+        Some(self.items.remove(idx))
+    }
+
+    /// Returns the smallest element without removing it.
+    pub fn peek_min(&self) -> Option<&T> {
+        self.items.first()
+    }
+
+    /// Returns the largest element without removing it, the one inserted first if there
+    /// are several.
+    pub fn peek_max(&self) -> Option<&T> {
+        self.items.get(self.max_idx()?)
+    }
+
+    /// Returns the index of the first of the largest elements.
+    fn max_idx(&self) -> Option<usize> {
+        let max = self.items.last()?;
+
+        Some(self.items.partition_point(|x| x < max))
+    }
+
+    /// Removes and returns the first element that is equal to `item`.
+    pub fn remove(&mut self, item: &T) -> Option<T> {
+        let idx = self.items.partition_point(|x| x < item);
+        match self.items.get(idx) {
+            Some(x) if x == item => Some(self.items.remove(idx)),
+            _ => None,
+        }
+    }
+
+    /// Removes and returns the first element for which `pred` returns `true`.
+    ///
+    /// Unlike [`SortedList::remove`], this can find an element by something other
+    /// than its ordering key, e.g. a thread in the sleep list by its id.
+    pub fn remove_by(&mut self, pred: impl FnMut(&T) -> bool) -> Option<T> {
+        let idx = self.items.iter().position(pred)?;
+
+        Some(self.items.remove(idx))
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the list holds no elements.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns an iterator over the elements in ascending order.
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.items.iter()
+    }
+}
+
+impl<T: Ord> Default for SortedList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
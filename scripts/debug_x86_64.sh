@@ -2,4 +2,4 @@ sh ./scripts/build_x86_64.sh
 
 qemu-system-x86_64 -drive format=raw,file=build/bios/pintos.img \
     -s -S \
-    -serial file:"qemu_log.txt" -device isa-debug-exit
\ No newline at end of file
+    -serial file:"qemu_log.txt" -serial file:"qemu_log_com2.txt" -device isa-debug-exit
\ No newline at end of file
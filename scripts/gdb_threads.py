@@ -0,0 +1,44 @@
+# Lists the kernel threads from the PINTOS_THREAD_TABLE symbol.
+# Usage: (gdb) source scripts/gdb_threads.py
+#        (gdb) info pintos-threads
+import gdb
+
+STATUS = ["running", "ready", "blocked", "dying"]
+MAGIC = 0x50544854
+HEADER_SIZE = 16
+
+
+class PintosThreads(gdb.Command):
+    """List the threads of the Pintos kernel."""
+
+    def __init__(self):
+        super().__init__("info pintos-threads", gdb.COMMAND_STATUS)
+
+    def invoke(self, arg, from_tty):
+        inferior = gdb.selected_inferior()
+        base = int(gdb.parse_and_eval("(unsigned long)&PINTOS_THREAD_TABLE"))
+
+        def u32(addr):
+            return int.from_bytes(inferior.read_memory(addr, 4).tobytes(), "little")
+
+        def u64(addr):
+            return int.from_bytes(inferior.read_memory(addr, 8).tobytes(), "little")
+
+        if u32(base) != MAGIC:
+            raise gdb.GdbError("PINTOS_THREAD_TABLE has an unexpected magic value")
+        count, size = u32(base + 8), u32(base + 12)
+
+        print("{:>5}  {:<8}  {:<18}  {}".format("id", "status", "stack", "name"))
+        for i in range(count):
+            entry = base + HEADER_SIZE + i * size
+            tid = u32(entry)
+            if tid == 0:
+                continue
+            status = u32(entry + 4)
+            status = STATUS[status] if status < len(STATUS) else str(status)
+            name_len = u32(entry + 16)
+            name = inferior.read_memory(entry + 20, name_len).tobytes().decode("utf-8", "replace")
+            print("{:>5}  {:<8}  {:#018x}  {}".format(tid, status, u64(entry + 8), name))
+
+
+PintosThreads()
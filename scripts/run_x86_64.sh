@@ -1,4 +1,30 @@
+# Usage: run_x86_64.sh [--dry-run]
+# With --dry-run, the image is built but QEMU is not started and its command is printed instead.
+#
+# The exit status tells whether the kernel succeeded. The kernel writes a value to the
+# isa-debug-exit port when shutting down and QEMU then exits with (value << 1) | 1:
+#   0x31 -> QEMU exits with 99  -> this script exits with 0 (success)
+#   0x32 -> QEMU exits with 101 -> this script exits with 1 (panic or failed test)
+# Any other status, e.g. QEMU failing to start, is passed through unchanged.
+DRY_RUN=0
+if [ "$1" = "--dry-run" ]; then
+    DRY_RUN=1
+fi
+
 sh ./scripts/build_x86_64.sh
 
-qemu-system-x86_64 -drive format=raw,file=build/bios/pintos.img \
-    -nographic -serial file:"qemu_log.txt" -device isa-debug-exit
\ No newline at end of file
+set -- qemu-system-x86_64 -drive format=raw,file=build/bios/pintos.img \
+    -nographic -serial file:"qemu_log.txt" -serial file:"qemu_log_com2.txt" -device isa-debug-exit
+
+if [ "$DRY_RUN" = 1 ]; then
+    echo "$@"
+    exit 0
+fi
+
+"$@"
+STATUS=$?
+case "$STATUS" in
+    99) exit 0 ;;
+    101) echo "the kernel reported a failure, see qemu_log.txt" >&2; exit 1 ;;
+    *) exit "$STATUS" ;;
+esac